@@ -19,27 +19,28 @@ class AudioSidecarExtension(GObject.GObject, Nautilus.MenuProvider):
     def menu_activate_cb(
             self,
             menu: Nautilus.MenuItem,
-            file: Nautilus.FileInfo,
+            files: List[Nautilus.FileInfo],
     ) -> None:
+        # one recording is made and shared with every selected file
         subprocess.Popen(
-            ['/speed/programs/audio-sidecar/target/release/audio_sidecar', file.get_location().get_path()],
+            ['/speed/programs/audio-sidecar/target/release/audio_sidecar', 'record', '--']
+            + [file.get_location().get_path() for file in files],
             cwd='/speed/programs/audio-sidecar/')
 
     def get_file_items(
             self,
             files: List[Nautilus.FileInfo],
     ) -> List[Nautilus.MenuItem]:
-        if len(files) != 1:
+        files = [file for file in files if file.get_location().get_path() is not None]
+        if len(files) == 0:
             return []
 
-        file = files[0]
-
         item = Nautilus.MenuItem(
             name="SimpleMenuExtension::Show_File_Name",
-            label="Record Audio",
-            tip="Record audio for this file. The audio will be named the same so it sorts next to the file",
+            label="Record Audio" if len(files) == 1 else "Record Audio for {} Files".format(len(files)),
+            tip="Record audio for the selected files. The audio will be named the same so it sorts next to each file",
         )
-        item.connect("activate", self.menu_activate_cb, file)
+        item.connect("activate", self.menu_activate_cb, files)
 
         return [
             item,
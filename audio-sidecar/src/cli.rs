@@ -0,0 +1,187 @@
+use crate::config::{ExistingFileStrategy, InterfacePattern, InterfacePatterns, ProgramConfig};
+use crate::headless::Limits;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage:
+    audio_sidecar [record] [OPTIONS] [--] FILE...
+                                             record audio for FILE, shared with any other FILEs
+    audio_sidecar narrate [OPTIONS] [--] DIRECTORY|FILE...
+                                             record audio for each file in turn, pressing Next to move on
+    audio_sidecar list-devices [OPTIONS]     list the audio interfaces that can record and which one would be used
+    audio_sidecar --version
+    audio_sidecar --help
+
+Options:
+    --config PATH              config file to use instead of ./audio-sidecar-config.toml
+    --device NAME              record from the interface whose name contains NAME, overrides Interface
+                               =NAME for the exact name, re:REGEX for a regular expression,
+                               auto for the one receiving sound
+    --strategy STRATEGY        what to do when FILE already has audio, overrides ExistingFileStrategy
+                               rename-to-last | rename-to-first | replace | append | ask
+    --include-recorded         narrate files that already have audio as well, only with narrate
+    --headless                 record without a window, press Enter to stop
+    --duration SECONDS         stop after this long, only with --headless
+    --silence-timeout SECONDS  stop after this long without sound, only with --headless";
+
+// the config file loaded when --config isn't given, config::File adds the .toml
+const DEFAULT_CONFIG_PATH: &str = "./audio-sidecar-config";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Record,
+    Narrate,
+    ListDevices,
+    Version,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub config_path: PathBuf,
+    pub filepaths: Vec<PathBuf>, // the audio is recorded for the first and shared with the rest
+    pub device: Option<InterfacePattern>,
+    pub strategy: Option<ExistingFileStrategy>,
+    pub include_recorded: bool, // narrate files that already have audio too
+    pub headless: bool, // record without a window, stopping on Enter, a signal or one of the limits
+    pub limits: Limits,
+}
+
+impl Args {
+    // command line flags win over the config file
+    pub fn apply_to(&self, config: &mut ProgramConfig) {
+        if let Some(device) = &self.device {
+            config.interface = InterfacePatterns::new(vec![device.clone()]);
+        }
+        if let Some(strategy) = self.strategy {
+            config.existing_file_strategy = strategy;
+        }
+    }
+}
+
+// the program's arguments without the program name
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut command = None;
+    let mut config_path = None;
+    let mut filepaths = Vec::new();
+    let mut device = None;
+    let mut strategy = None;
+    let mut include_recorded = false;
+    let mut headless = false;
+    let mut limits = Limits::default();
+
+    let mut args = args.peekable();
+
+    // record is the default so a file can be passed on its own, as file managers do
+    match args.peek().map(|arg| arg.as_str()) {
+        Some("record") => command = Some(Command::Record),
+        Some("narrate") => command = Some(Command::Narrate),
+        Some("list-devices") => command = Some(Command::ListDevices),
+        _ => {}
+    }
+    if command.is_some() {
+        args.next();
+    }
+
+    let mut only_files = false;
+
+    while let Some(arg) = args.next() {
+        // after -- everything is a file, even if it starts with -
+        if only_files {
+            filepaths.push(PathBuf::from(arg));
+            continue;
+        }
+
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--config" => config_path = Some(PathBuf::from(value()?)),
+            "--device" => {
+                let v = value()?;
+                device = Some(
+                    InterfacePattern::from_str(v.as_str())
+                        .map_err(|e| format!("Invalid --device: {}", e))?,
+                );
+            }
+            "--strategy" => {
+                let v = value()?;
+                strategy = Some(
+                    ExistingFileStrategy::from_str(v.as_str())
+                        .map_err(|_| format!("Unknown strategy \"{}\"", v))?,
+                );
+            }
+            "--" => only_files = true,
+            "--include-recorded" => include_recorded = true,
+            "--headless" => headless = true,
+            "--duration" | "--silence-timeout" => {
+                let v = value()?;
+                let seconds = f64::from_str(v.as_str())
+                    .ok()
+                    .filter(|s| *s > 0.0 && s.is_finite())
+                    .ok_or(format!("{} needs a number of seconds, not \"{}\"", arg, v))?;
+                if arg == "--duration" {
+                    limits.duration = Some(Duration::from_secs_f64(seconds));
+                } else {
+                    limits.silence_timeout = Some(Duration::from_secs_f64(seconds));
+                }
+            }
+            // kept as an option too since that's where people look for it
+            "--list-devices" => set_command(&mut command, Command::ListDevices)?,
+            "--version" => set_command(&mut command, Command::Version)?,
+            "--help" | "-h" => set_command(&mut command, Command::Help)?,
+            option if option.starts_with('-') && option != "-" => {
+                return Err(format!("Unknown option {}", option));
+            }
+            _ => filepaths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let command = command.unwrap_or(Command::Record);
+
+    let takes_files = command == Command::Record || command == Command::Narrate;
+    if takes_files && filepaths.is_empty() {
+        return Err(String::from("No file to record audio for"));
+    }
+    if !takes_files && !filepaths.is_empty() {
+        return Err(format!("{:?} doesn't take a file", command));
+    }
+    // like / or .., there's no name to give the sidecar. narrate lists what's in directories like these instead
+    let unnamed = filepaths.iter().find(|path| path.file_name().is_none());
+    if let Some(path) = unnamed.filter(|_| command == Command::Record) {
+        return Err(format!("\"{}\" is not a file", path.display()));
+    }
+    if command == Command::Narrate && headless {
+        return Err(String::from("narrate needs the window to show each file"));
+    }
+    if command != Command::Narrate && include_recorded {
+        return Err(String::from("--include-recorded only works with narrate"));
+    }
+    if !headless && (limits.duration.is_some() || limits.silence_timeout.is_some()) {
+        return Err(String::from(
+            "--duration and --silence-timeout only work with --headless",
+        ));
+    }
+
+    Ok(Args {
+        command,
+        config_path: config_path.unwrap_or(PathBuf::from(DEFAULT_CONFIG_PATH)),
+        filepaths,
+        device,
+        strategy,
+        include_recorded,
+        headless,
+        limits,
+    })
+}
+
+fn set_command(command: &mut Option<Command>, new: Command) -> Result<(), String> {
+    match command {
+        Some(existing) => Err(format!("Can't use {:?} and {:?} together", existing, new)),
+        None => {
+            *command = Some(new);
+            Ok(())
+        }
+    }
+}
@@ -1,28 +1,44 @@
 use config::{Config, FileFormat};
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 pub struct ProgramConfig {
-    pub interface: String, // search string for the audio interface to use
+    pub interface: InterfacePatterns, // which audio interface to use, see InterfacePatterns
     pub window_width: u32,
     pub window_height: u32,
     pub log_file: String,
     pub log_level: String,
     pub existing_file_strategy: ExistingFileStrategy,
+    pub filename_template: FilenameTemplate, // how sidecars are named, see FilenameTemplate
+    pub shared_audio: SharedAudio,
+    pub output_directory: Option<PathBuf>, // where sidecars are saved, relative paths are inside the source's folder
+    pub backup_directory: Option<PathBuf>, // where replaced sidecars go if they can't be moved to the trash
+    pub audio_format: FormatRequest, // what's left unset is recorded as the interface gives it
+    pub split_channels: bool,        // record each channel into its own mono sidecar
     pub shift_gain: i32,
 }
 
 // todo should we be wrapping the config like this? It seems like the underlying Config is meant to be used directly to allow hot-reloading
 impl ProgramConfig {
-    pub fn from_file() -> Result<ProgramConfig, String> {
+    pub fn from_file(path: &Path) -> Result<ProgramConfig, String> {
         let settings = Config::builder()
             .add_source(config::File::new(
-                "./audio-sidecar-config",
+                path.to_string_lossy().as_ref(),
                 FileFormat::Toml,
             ))
             .build()
-            .unwrap(); // todo this should have defaults and not panic if config file doesn't exist
+            .map_err(|e| format!("Could not read config file \"{}\": {}", path.display(), e))?; // todo this should have defaults if config file doesn't exist
 
-        let interface: String = settings.get("Interface").unwrap_or(String::from(""));
+        // a single pattern or a list of them in order of preference
+        let interface = match settings.get::<Vec<String>>("Interface") {
+            Ok(patterns) => patterns,
+            Err(_) => vec![settings.get("Interface").unwrap_or(String::from(""))],
+        };
+        let interface = InterfacePatterns::from_strs(&interface)
+            .map_err(|e| format!("Invalid Interface: {}", e))?;
         let window_width: u32 = settings.get("WindowWidth").unwrap_or(1200);
         let window_height: u32 = settings.get("WindowHeight").unwrap_or(600);
         let log_file: String = settings
@@ -39,6 +55,55 @@ impl ProgramConfig {
         )
         .unwrap_or(ExistingFileStrategy::RenameToLast);
 
+        let sidecar_naming = SidecarNaming::from_str(
+            settings
+                .get("SidecarNaming")
+                .unwrap_or(String::from(""))
+                .as_str(),
+        )
+        .unwrap_or(SidecarNaming::Stem);
+
+        // SidecarNaming picks between the two common templates, FilenameTemplate allows anything else
+        let filename_template = match settings.get::<String>("FilenameTemplate") {
+            Ok(template) if !template.is_empty() => {
+                FilenameTemplate::from_str(template.as_str())
+                    .map_err(|e| format!("Invalid FilenameTemplate \"{}\": {}", template, e))?
+            }
+            _ => match sidecar_naming {
+                SidecarNaming::Stem => FilenameTemplate::from_str("{stem}_audio{n}.flac")?,
+                SidecarNaming::WithExtension => FilenameTemplate::from_str("{name}_audio{n}.flac")?,
+            },
+        };
+
+        let shared_audio = SharedAudio::from_str(
+            settings
+                .get("SharedAudio")
+                .unwrap_or(String::from(""))
+                .as_str(),
+        )
+        .unwrap_or(SharedAudio::Hardlink);
+
+        let output_directory: Option<PathBuf> = settings
+            .get::<String>("OutputDirectory")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+
+        let backup_directory: Option<PathBuf> = settings
+            .get::<String>("BackupDirectory")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+
+        let audio_format = FormatRequest {
+            sample_rate: settings.get("SampleRate").ok(),
+            bits_per_sample: settings.get("BitsPerSample").ok(),
+            channels: settings.get("Channels").ok(),
+        };
+        audio_format.resolve(None)?;
+
+        let split_channels: bool = settings.get("SplitChannels").unwrap_or(false);
+
         Ok(ProgramConfig {
             interface,
             window_width,
@@ -46,12 +111,113 @@ impl ProgramConfig {
             log_file,
             log_level,
             existing_file_strategy,
+            filename_template,
+            shared_audio,
+            output_directory,
+            backup_directory,
+            audio_format,
+            split_channels,
             shift_gain
         })
     }
 }
 
-#[derive(Debug, PartialEq)]
+// the format sidecars are recorded in, the audio stream converts whatever the interface gives to it when they differ
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub bits_per_sample: u32,
+    pub channels: u32,
+}
+
+impl AudioFormat {
+    // limited to what both SDL and FLAC can do
+    pub fn new(sample_rate: u32, bits_per_sample: u32, channels: u32) -> Result<Self, String> {
+        if !(1..=655350).contains(&sample_rate) {
+            return Err(format!(
+                "SampleRate {} must be between 1 and 655350",
+                sample_rate
+            ));
+        }
+        // FLAC's streamable subset, which libFLAC won't encode outside of
+        if sample_rate >= 65536 && !sample_rate.is_multiple_of(10) {
+            return Err(format!(
+                "SampleRate {} must be a multiple of 10 above 65535",
+                sample_rate
+            ));
+        }
+        if ![8, 12, 16, 20, 24, 32].contains(&bits_per_sample) {
+            return Err(format!(
+                "BitsPerSample {} must be 8, 12, 16, 20, 24 or 32",
+                bits_per_sample
+            ));
+        }
+        if !(1..=8).contains(&channels) {
+            return Err(format!("Channels {} must be between 1 and 8", channels));
+        }
+
+        Ok(AudioFormat {
+            sample_rate,
+            bits_per_sample,
+            channels,
+        })
+    }
+
+    // length of some interleaved samples
+    pub fn seconds(&self, samples: u64) -> f64 {
+        samples as f64 / (self.sample_rate * self.channels) as f64
+    }
+
+    // interleaved samples in a duration, always whole frames
+    pub fn samples(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.sample_rate as f64) as u64 * self.channels as u64
+    }
+
+    // the loudest a sample can be
+    pub fn max_amplitude(&self) -> u32 {
+        (1u64 << (self.bits_per_sample - 1)) as u32 - 1
+    }
+}
+
+// SampleRate, BitsPerSample and Channels as set in the config. Each one that's set makes SDL resample, downmix or
+// requantize to it, the rest follow the interface's own format so nothing is converted unless asked for
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct FormatRequest {
+    pub sample_rate: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+impl FormatRequest {
+    // native is None when the interface's format isn't known, then it's 44100 Hz, 24 bit, mono
+    pub fn resolve(&self, native: Option<AudioFormat>) -> Result<AudioFormat, String> {
+        let native = native.unwrap_or(AudioFormat {
+            sample_rate: 44100,
+            bits_per_sample: 24,
+            channels: 1,
+        });
+        AudioFormat::new(
+            self.sample_rate.unwrap_or(native.sample_rate),
+            self.bits_per_sample.unwrap_or(native.bits_per_sample),
+            self.channels.unwrap_or(native.channels),
+        )
+    }
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz {} bit {} channel{}",
+            self.sample_rate,
+            self.bits_per_sample,
+            self.channels,
+            if self.channels == 1 { "" } else { "s" }
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ExistingFileStrategy {
     RenameToLast,
     RenameToFirst,
@@ -73,3 +239,354 @@ impl FromStr for ExistingFileStrategy {
         }
     }
 }
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SidecarNaming {
+    Stem,          // photo.jpg -> photo_audio.flac
+    WithExtension, // photo.jpg -> photo.jpg_audio.flac
+}
+
+impl FromStr for SidecarNaming {
+    type Err = ();
+    fn from_str(s: &str) -> Result<SidecarNaming, ()> {
+        match s {
+            "stem" => Ok(SidecarNaming::Stem),
+            "with-extension" => Ok(SidecarNaming::WithExtension),
+            _ => Err(()),
+        }
+    }
+}
+
+// how the other files get their sidecar when one recording is made for several files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SharedAudio {
+    Hardlink, // falls back to Copy when the files are on different filesystems
+    Symlink,
+    Copy,
+}
+
+impl FromStr for SharedAudio {
+    type Err = ();
+    fn from_str(s: &str) -> Result<SharedAudio, ()> {
+        match s {
+            "hardlink" => Ok(SharedAudio::Hardlink),
+            "symlink" => Ok(SharedAudio::Symlink),
+            "copy" => Ok(SharedAudio::Copy),
+            _ => Err(()),
+        }
+    }
+}
+
+// one piece of a FilenameTemplate
+#[derive(Debug, PartialEq, Clone)]
+pub enum TemplatePart {
+    Text(String),
+    Stem,   // {stem} file name of the source without the extension
+    Ext,    // {ext} extension of the source without the dot
+    Name,   // {name} whole file name of the source
+    Number, // {n} empty for the first sidecar, then 2, 3, 4...
+    Date,   // {date} YYYY-MM-DD when the recording started
+    Time,   // {time} HH-MM-SS when the recording started
+    Device, // {device} name of the audio interface
+}
+
+// one way of naming an audio interface, compared without regard to case
+#[derive(Debug, Clone)]
+pub enum InterfacePattern {
+    Contains(String), // text found anywhere in the name
+    Regex(Regex),     // "re:" then a regular expression the name matches
+    Exact(String),    // "=" then the whole name
+    Auto,             // "auto", the interface receiving sound
+}
+
+impl InterfacePattern {
+    // auto doesn't go by the name, it has to be listened for
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            InterfacePattern::Contains(text) => name.to_lowercase().contains(&text.to_lowercase()),
+            InterfacePattern::Regex(regex) => regex.is_match(name),
+            InterfacePattern::Exact(text) => name.to_lowercase() == text.to_lowercase(),
+            InterfacePattern::Auto => false,
+        }
+    }
+}
+
+impl FromStr for InterfacePattern {
+    type Err = String;
+    fn from_str(s: &str) -> Result<InterfacePattern, String> {
+        if s == "auto" {
+            Ok(InterfacePattern::Auto)
+        } else if let Some(regex) = s.strip_prefix("re:") {
+            RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .map(InterfacePattern::Regex)
+                .map_err(|e| format!("bad regular expression \"{}\": {}", regex, e))
+        } else if let Some(name) = s.strip_prefix('=') {
+            Ok(InterfacePattern::Exact(name.to_string()))
+        } else {
+            Ok(InterfacePattern::Contains(s.to_string()))
+        }
+    }
+}
+
+// as it's written in the config
+impl fmt::Display for InterfacePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterfacePattern::Contains(text) => write!(f, "\"{}\"", text),
+            InterfacePattern::Regex(regex) => write!(f, "\"re:{}\"", regex.as_str()),
+            InterfacePattern::Exact(name) => write!(f, "\"={}\"", name),
+            InterfacePattern::Auto => write!(f, "\"auto\""),
+        }
+    }
+}
+
+// the Interface setting, the first pattern with an available device picks the interface
+#[derive(Debug, Clone)]
+pub struct InterfacePatterns {
+    patterns: Vec<InterfacePattern>,
+}
+
+impl InterfacePatterns {
+    pub fn new(patterns: Vec<InterfacePattern>) -> Self {
+        InterfacePatterns { patterns }
+    }
+
+    pub fn from_strs(patterns: &[String]) -> Result<Self, String> {
+        let patterns = patterns
+            .iter()
+            .map(|p| InterfacePattern::from_str(p.as_str()))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(InterfacePatterns { patterns })
+    }
+
+    pub fn patterns(&self) -> &[InterfacePattern] {
+        &self.patterns
+    }
+
+    // just "auto", nothing goes by name
+    pub fn is_auto(&self) -> bool {
+        matches!(self.patterns.as_slice(), [InterfacePattern::Auto])
+    }
+}
+
+impl fmt::Display for InterfacePatterns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.patterns.as_slice() {
+            [pattern] => write!(f, "{}", pattern),
+            patterns => {
+                let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+                write!(f, "[{}]", patterns.join(", "))
+            }
+        }
+    }
+}
+
+// file name of the sidecars, e.g. "{stem}_audio{n}.flac" names the sidecars of photo.jpg photo_audio.flac, photo_audio2.flac...
+#[derive(Debug, PartialEq, Clone)]
+pub struct FilenameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl FilenameTemplate {
+    pub fn parts(&self) -> &[TemplatePart] {
+        &self.parts
+    }
+
+    // whether photo.jpg and photo.png get different sidecars
+    pub fn includes_extension(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| *p == TemplatePart::Ext || *p == TemplatePart::Name)
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+    fn from_str(s: &str) -> Result<FilenameTemplate, String> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or(String::from("missing closing }"))?
+                + start;
+
+            parts.push(match &rest[start + 1..end] {
+                "stem" => TemplatePart::Stem,
+                "ext" => TemplatePart::Ext,
+                "name" => TemplatePart::Name,
+                "n" => TemplatePart::Number,
+                "date" => TemplatePart::Date,
+                "time" => TemplatePart::Time,
+                "device" => TemplatePart::Device,
+                other => return Err(format!("unknown placeholder {{{}}}", other)),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+
+        if parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Text(t) if t.contains('/') || t.contains('}')))
+        {
+            return Err(String::from("must not contain / or unmatched }"));
+        }
+        // without a number every recording after the first would have nowhere to go
+        if !parts.contains(&TemplatePart::Number) {
+            return Err(String::from("must contain {n}"));
+        }
+        if !parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Stem | TemplatePart::Name))
+        {
+            return Err(String::from("must contain {stem} or {name}"));
+        }
+        // leading dots would hide the sidecar and clash with the partial files, see flac::partial_path
+        if matches!(parts.first(), Some(TemplatePart::Text(t)) if t.starts_with('.')) {
+            return Err(String::from("must not start with ."));
+        }
+        // {name}{n} or {stem}.{ext} would make the first sidecar the source file itself
+        if !parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Text(t) if t.chars().any(|c| c != '.')))
+        {
+            return Err(String::from(
+                "must contain text of its own like _audio, or the sidecar could be named the same as the source",
+            ));
+        }
+
+        Ok(FilenameTemplate { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filename_templates_are_parsed() {
+        let template = FilenameTemplate::from_str("{stem}_{date}_audio{n}.flac").unwrap();
+        assert_eq!(
+            template.parts(),
+            [
+                TemplatePart::Stem,
+                TemplatePart::Text(String::from("_")),
+                TemplatePart::Date,
+                TemplatePart::Text(String::from("_audio")),
+                TemplatePart::Number,
+                TemplatePart::Text(String::from(".flac")),
+            ]
+        );
+        assert!(!template.includes_extension());
+
+        for template in [
+            "{stem}_audio{n}.flac",
+            "{name}_audio{n}.flac",
+            "{stem}.{ext}_{device}_{time}{n}.flac",
+            "audio_{n}_{stem}",
+        ] {
+            assert!(
+                FilenameTemplate::from_str(template).is_ok(),
+                "{} was rejected",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn bad_filename_templates_are_rejected() {
+        for template in [
+            "{stem}_audio{n.flac",        // unclosed {
+            "{stem}_audio{n}.flac}",      // unmatched }
+            "{stem}_{song}{n}.flac",      // unknown placeholder
+            "{stem}_audio.flac",          // no {n}
+            "audio{n}.flac",              // no {stem} or {name}
+            ".{stem}_audio{n}.flac",      // hidden
+            "audio/{stem}_audio{n}.flac", // a directory
+            // the first sidecar would be the source itself
+            "{name}{n}",
+            "{stem}{n}.{ext}",
+            "{stem}{n}",
+            "{stem}.{n}{ext}",
+        ] {
+            assert!(
+                FilenameTemplate::from_str(template).is_err(),
+                "{} was accepted",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn audio_format_is_limited_to_what_flac_encodes() {
+        for bits in [8, 12, 16, 20, 24, 32] {
+            assert!(AudioFormat::new(44100, bits, 1).is_ok(), "{} bit", bits);
+        }
+        for bits in [0, 7, 10, 18, 31, 33] {
+            assert!(AudioFormat::new(44100, bits, 1).is_err(), "{} bit", bits);
+        }
+
+        assert!(AudioFormat::new(96000, 24, 2).is_ok());
+        assert!(AudioFormat::new(65535, 24, 2).is_ok());
+        assert!(AudioFormat::new(88201, 24, 2).is_err());
+        assert!(AudioFormat::new(0, 24, 2).is_err());
+        assert!(AudioFormat::new(44100, 24, 9).is_err());
+    }
+
+    #[test]
+    fn unset_format_follows_the_interface() {
+        let native = AudioFormat::new(48000, 16, 2).unwrap();
+        let request = FormatRequest {
+            bits_per_sample: Some(24),
+            ..Default::default()
+        };
+        assert_eq!(
+            request.resolve(Some(native)),
+            AudioFormat::new(48000, 24, 2)
+        );
+        assert_eq!(
+            FormatRequest::default().resolve(None),
+            AudioFormat::new(44100, 24, 1)
+        );
+    }
+
+    #[test]
+    fn interface_patterns_match_names() {
+        let pattern = |s: &str| InterfacePattern::from_str(s).unwrap();
+
+        let contains = pattern("scarlett");
+        assert!(matches!(contains, InterfacePattern::Contains(_)));
+        assert!(contains.matches("Focusrite Scarlett 2i2"));
+        assert!(contains.matches("SCARLETT"));
+        assert!(!contains.matches("Built-in Microphone"));
+
+        let exact = pattern("=Scarlett 2i2");
+        assert!(matches!(exact, InterfacePattern::Exact(_)));
+        assert!(exact.matches("scarlett 2I2"));
+        assert!(!exact.matches("Scarlett 2i2 USB"));
+        assert!(!exact.matches("Scarlett"));
+
+        let regex = pattern("re:^scarlett \\d+i\\d+$");
+        assert!(matches!(regex, InterfacePattern::Regex(_)));
+        assert!(regex.matches("Scarlett 18i20"));
+        assert!(!regex.matches("Focusrite Scarlett 2i2"));
+
+        let auto = pattern("auto");
+        assert!(matches!(auto, InterfacePattern::Auto));
+        assert!(!auto.matches("auto"));
+    }
+
+    #[test]
+    fn bad_interface_regex_is_rejected() {
+        assert!(InterfacePattern::from_str("re:scarlett (2i2").is_err());
+        assert!(InterfacePattern::from_str("re:[").is_err());
+    }
+}
@@ -1,14 +1,41 @@
+use crate::utils::{parent_dir, sync_directory, try_lock_file};
 use flac_sys::{
-    FLAC__StreamEncoder, FLAC__StreamEncoderInitStatus_FLAC__STREAM_ENCODER_INIT_STATUS_OK,
-    FLAC__StreamEncoderState, FLAC__stream_encoder_delete, FLAC__stream_encoder_finish,
-    FLAC__stream_encoder_get_state, FLAC__stream_encoder_init_file, FLAC__stream_encoder_new,
-    FLAC__stream_encoder_process, FLAC__stream_encoder_set_bits_per_sample,
-    FLAC__stream_encoder_set_channels, FLAC__stream_encoder_set_compression_level,
+    FLAC__Frame, FLAC__MetadataType_FLAC__METADATA_TYPE_PADDING,
+    FLAC__MetadataType_FLAC__METADATA_TYPE_STREAMINFO,
+    FLAC__MetadataType_FLAC__METADATA_TYPE_VORBIS_COMMENT, FLAC__Metadata_Chain,
+    FLAC__Metadata_Iterator, FLAC__StreamDecoder, FLAC__StreamDecoderErrorStatus,
+    FLAC__StreamDecoderInitStatus_FLAC__STREAM_DECODER_INIT_STATUS_OK,
+    FLAC__StreamDecoderWriteStatus,
+    FLAC__StreamDecoderWriteStatus_FLAC__STREAM_DECODER_WRITE_STATUS_CONTINUE, FLAC__StreamEncoder,
+    FLAC__StreamEncoderInitStatus_FLAC__STREAM_ENCODER_INIT_STATUS_OK, FLAC__StreamEncoderState,
+    FLAC__StreamMetadata, FLAC__StreamMetadata_VorbisComment_Entry, FLAC__bool, FLAC__int32,
+    FLAC__metadata_chain_delete, FLAC__metadata_chain_new, FLAC__metadata_chain_read,
+    FLAC__metadata_chain_status, FLAC__metadata_chain_write, FLAC__metadata_iterator_delete,
+    FLAC__metadata_iterator_get_block_type, FLAC__metadata_iterator_init,
+    FLAC__metadata_iterator_new, FLAC__metadata_iterator_next, FLAC__metadata_iterator_set_block,
+    FLAC__metadata_object_delete, FLAC__metadata_object_new,
+    FLAC__metadata_object_vorbiscomment_append_comment,
+    FLAC__metadata_object_vorbiscomment_entry_from_name_value_pair, FLAC__stream_decoder_delete,
+    FLAC__stream_decoder_finish, FLAC__stream_decoder_get_state, FLAC__stream_decoder_init_file,
+    FLAC__stream_decoder_new, FLAC__stream_decoder_process_until_end_of_stream,
+    FLAC__stream_decoder_set_md5_checking, FLAC__stream_encoder_delete,
+    FLAC__stream_encoder_finish, FLAC__stream_encoder_get_state, FLAC__stream_encoder_init_file,
+    FLAC__stream_encoder_new, FLAC__stream_encoder_process_interleaved,
+    FLAC__stream_encoder_set_bits_per_sample, FLAC__stream_encoder_set_channels,
+    FLAC__stream_encoder_set_compression_level, FLAC__stream_encoder_set_metadata,
     FLAC__stream_encoder_set_sample_rate,
 };
-use std::ffi::CString;
+use log::{error, warn};
+use std::ffi::{c_void, CString};
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::ptr;
+use std::sync::{Mutex, TryLockError};
+
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+// room left after the comments so they can be rewritten in place when they change, such as after switching interface
+const COMMENT_PADDING: u32 = 1024;
 
 pub struct EncoderConfig {
     output_path: Option<PathBuf>,
@@ -16,10 +43,40 @@ pub struct EncoderConfig {
     bits_per_sample: u32,
     channels: u32,
     compression_level: u32,
+    comments: Vec<(String, String)>, // name and value pairs for the VORBIS_COMMENT block
 }
 
 pub struct Encoder {
     stream_encoder: *mut FLAC__StreamEncoder,
+    _lock: File, // tells other instances the partial file is still being recorded, see recovery::find_orphans
+    metadata: Vec<*mut FLAC__StreamMetadata>, // empty without comments, must outlive the stream encoder
+    changed_comments: Option<Vec<(String, String)>>, // written over the ones the file was started with when finished
+    channels: u32,
+    output_path: PathBuf,
+    partial_path: PathBuf,
+}
+
+// encoders that have been started but not finished, so they can still be finished if the program panics
+static UNFINISHED: Mutex<Vec<Unfinished>> = Mutex::new(Vec::new());
+
+struct Unfinished {
+    stream_encoder: *mut FLAC__StreamEncoder,
+    metadata: Vec<*mut FLAC__StreamMetadata>,
+    changed_comments: Option<Vec<(String, String)>>,
+    output_path: PathBuf,
+    partial_path: PathBuf,
+}
+
+// the stream encoder is only used from the main thread, the mutex just satisfies the static
+unsafe impl Send for Unfinished {}
+
+// the hidden file audio is written to while recording: /dir/name_audio.flac is recorded into /dir/.name_audio.flac.partial
+// it is only renamed to the output path once it is complete, so a file with the real name is never half written
+pub fn partial_path(output_path: &Path) -> PathBuf {
+    let mut file_name = std::ffi::OsString::from(".");
+    file_name.push(output_path.file_name().unwrap_or_default());
+    file_name.push(PARTIAL_SUFFIX);
+    output_path.with_file_name(file_name)
 }
 
 fn get_state(stream_encoder: *mut FLAC__StreamEncoder) -> FLAC__StreamEncoderState {
@@ -34,6 +91,7 @@ impl EncoderConfig {
             sample_rate: 44100,
             bits_per_sample: 24,
             compression_level: 8,
+            comments: Vec::new(),
         }
     }
 
@@ -41,16 +99,66 @@ impl EncoderConfig {
         self.output_path = Some(output_path.into());
     }
 
+    pub fn set_format(&mut self, sample_rate: u32, bits_per_sample: u32, channels: u32) {
+        self.sample_rate = sample_rate;
+        self.bits_per_sample = bits_per_sample;
+        self.channels = channels;
+    }
+
+    // tag the file, name must be printable ASCII without '='
+    pub fn add_comment(&mut self, name: &str, value: &str) {
+        self.comments.push((name.to_string(), value.to_string()));
+    }
+
+    // true if audio decoded from an existing file can be carried forward into this encoder as-is
+    pub fn accepts(&self, audio: &DecodedAudio) -> bool {
+        audio.sample_rate == self.sample_rate
+            && audio.bits_per_sample == self.bits_per_sample
+            && audio.channels == self.channels
+    }
+
     pub fn get_encoder(self) -> Result<Encoder, String> {
-        let path = self
+        let output_path = self
             .output_path
             .clone()
             .ok_or("Output path must be set".to_string())?;
-        let output_path_cstring = CString::new(path.display().to_string())
-            .expect("output path to be converted to CString");
+        let partial_path = partial_path(&output_path);
+        let partial_path_cstring = CString::new(partial_path.display().to_string())
+            .expect("partial path to be converted to CString");
+
+        // taken before libFLAC truncates the file so another instance's recording is never cut short
+        let lock = match try_lock_file(&partial_path) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                return Err(format!(
+                    "\"{}\" is being recorded by another instance",
+                    partial_path.display()
+                ));
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Could not lock \"{}\": {}",
+                    partial_path.display(),
+                    e
+                ))
+            }
+        };
+
+        let mut metadata = Vec::new();
+        if !self.comments.is_empty() {
+            metadata.push(vorbis_comment(&self.comments)?);
+            match padding(COMMENT_PADDING) {
+                Ok(block) => metadata.push(block),
+                Err(msg) => {
+                    delete_metadata(&metadata);
+                    return Err(msg);
+                }
+            }
+        }
 
         let stream_encoder = unsafe { FLAC__stream_encoder_new() };
         if stream_encoder.is_null() {
+            delete_metadata(&metadata);
             return Err(
                 "Could not initialize Flac stream encoder. Stream Encoder is null.".to_string(),
             );
@@ -62,32 +170,64 @@ impl EncoderConfig {
             FLAC__stream_encoder_set_channels(stream_encoder, self.channels);
             FLAC__stream_encoder_set_sample_rate(stream_encoder, self.sample_rate);
             FLAC__stream_encoder_set_bits_per_sample(stream_encoder, self.bits_per_sample);
+            if !metadata.is_empty() {
+                // the array is copied but the blocks aren't, they're deleted once the encoder is finished
+                let mut blocks = metadata.clone();
+                FLAC__stream_encoder_set_metadata(
+                    stream_encoder,
+                    blocks.as_mut_ptr(),
+                    blocks.len() as u32,
+                );
+            }
         }
 
         let init_status = unsafe {
             FLAC__stream_encoder_init_file(
                 stream_encoder,
-                output_path_cstring.as_ptr(),
+                partial_path_cstring.as_ptr(),
                 None,
                 ptr::null_mut(),
             )
         };
 
         if init_status != FLAC__StreamEncoderInitStatus_FLAC__STREAM_ENCODER_INIT_STATUS_OK {
+            unsafe { FLAC__stream_encoder_delete(stream_encoder) };
+            delete_metadata(&metadata);
             return Err(format!(
                 "Stream Encoder file initialization failed. Status: {:?}",
                 init_status
             ));
         }
 
-        Ok(Encoder { stream_encoder })
+        UNFINISHED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(Unfinished {
+                stream_encoder,
+                metadata: metadata.clone(),
+                changed_comments: None,
+                output_path: output_path.clone(),
+                partial_path: partial_path.clone(),
+            });
+
+        Ok(Encoder {
+            stream_encoder,
+            _lock: lock,
+            metadata,
+            changed_comments: None,
+            channels: self.channels,
+            output_path,
+            partial_path,
+        })
     }
 }
 
 impl Encoder {
-    pub fn encode(&self, data: &Vec<i32>) -> Result<(), String> {
+    // data is interleaved when there are several channels, and always whole frames
+    pub fn encode(&self, data: &[i32]) -> Result<(), String> {
+        let frames = data.len() as u32 / self.channels;
         let success = unsafe {
-            FLAC__stream_encoder_process(self.stream_encoder, &data.as_ptr(), data.len() as u32)
+            FLAC__stream_encoder_process_interleaved(self.stream_encoder, data.as_ptr(), frames)
         } != 0;
 
         if success {
@@ -98,22 +238,406 @@ impl Encoder {
         }
     }
 
+    // replace every comment the file was started with. The file is only changed once it's finished
+    pub fn set_comments(&mut self, comments: Vec<(String, String)>) {
+        let mut unfinished = UNFINISHED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(u) = unfinished
+            .iter_mut()
+            .find(|u| u.stream_encoder == self.stream_encoder)
+        {
+            u.changed_comments = Some(comments.clone());
+        }
+
+        self.changed_comments = Some(comments);
+    }
+
     // finish takes ownership of self and drops it since it will be invalid after this function
-    pub fn finish(self) -> Result<(), String> {
-        let success = unsafe { FLAC__stream_encoder_finish(self.stream_encoder) } != 0;
+    // returns where the file was saved
+    pub fn finish(self) -> Result<PathBuf, String> {
+        UNFINISHED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|unfinished| unfinished.stream_encoder != self.stream_encoder);
 
-        if success {
-            unsafe { FLAC__stream_encoder_delete(self.stream_encoder) };
-            Ok(())
-        } else {
-            let state = get_state(self.stream_encoder);
+        finish_stream_encoder(
+            self.stream_encoder,
+            &self.metadata,
+            self.changed_comments.as_deref(),
+            &self.partial_path,
+            &self.output_path,
+        )?;
+
+        Ok(self.output_path)
+    }
+}
+
+// once the file is complete and on disk it is renamed from the partial path to the output path
+fn finish_stream_encoder(
+    stream_encoder: *mut FLAC__StreamEncoder,
+    metadata: &[*mut FLAC__StreamMetadata],
+    changed_comments: Option<&[(String, String)]>,
+    partial_path: &Path,
+    output_path: &Path,
+) -> Result<(), String> {
+    let success = unsafe { FLAC__stream_encoder_finish(stream_encoder) } != 0;
+    delete_metadata(metadata);
+
+    if success {
+        unsafe { FLAC__stream_encoder_delete(stream_encoder) };
+    } else {
+        let state = get_state(stream_encoder);
+
+        unsafe { FLAC__stream_encoder_delete(stream_encoder) };
+
+        return Err(format!(
+            "Failed to finish encoding. Encoder state: {}",
+            state
+        ));
+    }
+
+    // outdated comments aren't worth losing the recording over
+    if let Some(Err(msg)) =
+        changed_comments.map(|comments| rewrite_comments(partial_path, comments))
+    {
+        warn!(
+            "Could not update the comments in \"{}\": {}",
+            partial_path.display(),
+            msg
+        );
+    }
+
+    File::open(partial_path)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| {
+            format!(
+                "Failed to sync \"{}\" to disk: {}",
+                partial_path.display(),
+                e
+            )
+        })?;
+
+    std::fs::rename(partial_path, output_path).map_err(|e| {
+        format!(
+            "Failed to rename \"{}\" to \"{}\": {}",
+            partial_path.display(),
+            output_path.display(),
+            e
+        )
+    })?;
+
+    let dir = parent_dir(output_path);
+    sync_directory(dir).map_err(|e| format!("Failed to sync \"{}\": {}", dir.display(), e))
+}
+
+// the blocks must not be deleted while an encoder still uses them
+fn delete_metadata(metadata: &[*mut FLAC__StreamMetadata]) {
+    for &block in metadata {
+        unsafe { FLAC__metadata_object_delete(block) };
+    }
+}
+
+// a PADDING block of length zeroed bytes, for the caller to delete with delete_metadata
+fn padding(length: u32) -> Result<*mut FLAC__StreamMetadata, String> {
+    let metadata =
+        unsafe { FLAC__metadata_object_new(FLAC__MetadataType_FLAC__METADATA_TYPE_PADDING) };
+    if metadata.is_null() {
+        return Err("Could not allocate the PADDING block".to_string());
+    }
+    unsafe { (*metadata).length = length };
+    Ok(metadata)
+}
+
+// a VORBIS_COMMENT block holding the comments, for the caller to delete with delete_metadata
+fn vorbis_comment(comments: &[(String, String)]) -> Result<*mut FLAC__StreamMetadata, String> {
+    let metadata =
+        unsafe { FLAC__metadata_object_new(FLAC__MetadataType_FLAC__METADATA_TYPE_VORBIS_COMMENT) };
+    if metadata.is_null() {
+        return Err("Could not allocate the VORBIS_COMMENT block".to_string());
+    }
+
+    for (name, value) in comments {
+        let mut entry = FLAC__StreamMetadata_VorbisComment_Entry {
+            length: 0,
+            entry: ptr::null_mut(),
+        };
+        // the block takes ownership of the entry since it isn't copied
+        let added = match (CString::new(name.as_str()), CString::new(value.as_str())) {
+            (Ok(name), Ok(value)) => unsafe {
+                FLAC__metadata_object_vorbiscomment_entry_from_name_value_pair(
+                    &mut entry,
+                    name.as_ptr(),
+                    value.as_ptr(),
+                ) != 0
+                    && FLAC__metadata_object_vorbiscomment_append_comment(metadata, entry, 0) != 0
+            },
+            _ => false,
+        };
+
+        if !added {
+            unsafe { FLAC__metadata_object_delete(metadata) };
+            return Err(format!("Could not add comment {}={}", name, value));
+        }
+    }
+
+    Ok(metadata)
+}
 
-            unsafe { FLAC__stream_encoder_delete(self.stream_encoder) };
+// replace the VORBIS_COMMENT block of a finished file. It's written in place as long as the padding after it is enough
+fn rewrite_comments(path: &Path, comments: &[(String, String)]) -> Result<(), String> {
+    let path_cstring =
+        CString::new(path.display().to_string()).expect("path to be converted to CString");
 
-            Err(format!(
-                "Failed to finish encoding. Encoder state: {}",
-                state
-            ))
+    let chain = unsafe { FLAC__metadata_chain_new() };
+    if chain.is_null() {
+        return Err("Could not allocate the metadata chain".to_string());
+    }
+    let iterator = unsafe { FLAC__metadata_iterator_new() };
+    if iterator.is_null() {
+        unsafe { FLAC__metadata_chain_delete(chain) };
+        return Err("Could not allocate the metadata iterator".to_string());
+    }
+
+    let result = replace_comments(chain, iterator, &path_cstring, comments);
+
+    unsafe {
+        FLAC__metadata_iterator_delete(iterator);
+        FLAC__metadata_chain_delete(chain);
+    }
+    result
+}
+
+fn replace_comments(
+    chain: *mut FLAC__Metadata_Chain,
+    iterator: *mut FLAC__Metadata_Iterator,
+    path: &CString,
+    comments: &[(String, String)],
+) -> Result<(), String> {
+    if unsafe { FLAC__metadata_chain_read(chain, path.as_ptr()) } == 0 {
+        let status = unsafe { FLAC__metadata_chain_status(chain) };
+        return Err(format!(
+            "Failed to read the metadata. Chain status: {}",
+            status
+        ));
+    }
+
+    unsafe { FLAC__metadata_iterator_init(iterator, chain) };
+    while unsafe { FLAC__metadata_iterator_get_block_type(iterator) }
+        != FLAC__MetadataType_FLAC__METADATA_TYPE_VORBIS_COMMENT
+    {
+        if unsafe { FLAC__metadata_iterator_next(iterator) } == 0 {
+            return Err("There is no VORBIS_COMMENT block to replace".to_string());
+        }
+    }
+
+    // the chain takes ownership of the block once it's set
+    let block = vorbis_comment(comments)?;
+    if unsafe { FLAC__metadata_iterator_set_block(iterator, block) } == 0 {
+        delete_metadata(&[block]);
+        return Err("Could not replace the VORBIS_COMMENT block".to_string());
+    }
+
+    if unsafe { FLAC__metadata_chain_write(chain, 1, 0) } == 0 {
+        let status = unsafe { FLAC__metadata_chain_status(chain) };
+        return Err(format!(
+            "Failed to write the metadata. Chain status: {}",
+            status
+        ));
+    }
+
+    Ok(())
+}
+
+// finish every encoder that is still recording so the audio so far is saved as a playable file
+// this is for when the program is about to die. Anything that can't be finished is left to be recovered on the next launch
+pub fn finish_unfinished() {
+    let unfinished = match UNFINISHED.try_lock() {
+        Ok(mut unfinished) => std::mem::take(&mut *unfinished),
+        Err(TryLockError::Poisoned(poisoned)) => std::mem::take(&mut *poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => return,
+    };
+
+    for u in unfinished {
+        match finish_stream_encoder(
+            u.stream_encoder,
+            &u.metadata,
+            u.changed_comments.as_deref(),
+            &u.partial_path,
+            &u.output_path,
+        ) {
+            Ok(()) => error!(
+                "Saved the audio recorded so far to \"{}\"",
+                u.output_path.display()
+            ),
+            Err(msg) => error!(
+                "Could not save the audio recorded so far to \"{}\": {}",
+                u.output_path.display(),
+                msg
+            ),
+        }
+    }
+}
+
+// interleaved samples read back from a flac file along with the format they were stored in
+pub struct DecodedAudio {
+    pub samples: Vec<i32>,
+    pub sample_rate: u32,
+    pub bits_per_sample: u32,
+    pub channels: u32,
+}
+
+// state shared with the decoder callbacks through the client_data pointer
+struct DecodeState {
+    audio: DecodedAudio,
+    error: Option<FLAC__StreamDecoderErrorStatus>,
+}
+
+unsafe extern "C" fn decoder_write_callback(
+    _decoder: *const FLAC__StreamDecoder,
+    frame: *const FLAC__Frame,
+    buffer: *const *const FLAC__int32,
+    client_data: *mut c_void,
+) -> FLAC__StreamDecoderWriteStatus {
+    let state = unsafe { &mut *(client_data as *mut DecodeState) };
+    let header = unsafe { (*frame).header };
+
+    let channels = header.channels as usize;
+    let blocksize = header.blocksize as usize;
+    let channel_buffers = unsafe { std::slice::from_raw_parts(buffer, channels) };
+
+    state.audio.samples.reserve(blocksize * channels);
+    for i in 0..blocksize {
+        for channel in channel_buffers {
+            state.audio.samples.push(unsafe { *channel.add(i) });
+        }
+    }
+
+    FLAC__StreamDecoderWriteStatus_FLAC__STREAM_DECODER_WRITE_STATUS_CONTINUE
+}
+
+unsafe extern "C" fn decoder_metadata_callback(
+    _decoder: *const FLAC__StreamDecoder,
+    metadata: *const FLAC__StreamMetadata,
+    client_data: *mut c_void,
+) {
+    let state = unsafe { &mut *(client_data as *mut DecodeState) };
+    let metadata = unsafe { &*metadata };
+
+    if metadata.type_ == FLAC__MetadataType_FLAC__METADATA_TYPE_STREAMINFO {
+        let stream_info = unsafe { metadata.data.stream_info };
+        state.audio.sample_rate = stream_info.sample_rate;
+        state.audio.bits_per_sample = stream_info.bits_per_sample;
+        state.audio.channels = stream_info.channels;
+        state
+            .audio
+            .samples
+            .reserve((stream_info.total_samples * stream_info.channels as u64) as usize);
+    }
+}
+
+unsafe extern "C" fn decoder_error_callback(
+    _decoder: *const FLAC__StreamDecoder,
+    status: FLAC__StreamDecoderErrorStatus,
+    client_data: *mut c_void,
+) {
+    let state = unsafe { &mut *(client_data as *mut DecodeState) };
+    // keep the first error, later ones are usually a consequence of it
+    state.error.get_or_insert(status);
+}
+
+// decode an entire flac file into memory
+pub fn decode_file(path: &Path) -> Result<DecodedAudio, String> {
+    decode(path, true)
+}
+
+// decode as much as possible of a file that was never finished, such as when the program died mid-recording
+// the STREAMINFO of these has no sample count or MD5 and the last frame may be cut off
+pub fn recover_file(path: &Path) -> Result<DecodedAudio, String> {
+    decode(path, false)
+}
+
+fn decode(path: &Path, strict: bool) -> Result<DecodedAudio, String> {
+    let input_path_cstring =
+        CString::new(path.display().to_string()).expect("input path to be converted to CString");
+
+    let stream_decoder = unsafe { FLAC__stream_decoder_new() };
+    if stream_decoder.is_null() {
+        return Err(
+            "Could not initialize Flac stream decoder. Stream Decoder is null.".to_string(),
+        );
+    }
+
+    // boxed so the callbacks have a stable address to write into
+    let mut state = Box::new(DecodeState {
+        audio: DecodedAudio {
+            samples: Vec::new(),
+            sample_rate: 0,
+            bits_per_sample: 0,
+            channels: 0,
+        },
+        error: None,
+    });
+
+    unsafe {
+        FLAC__stream_decoder_set_md5_checking(stream_decoder, strict as FLAC__bool);
+    }
+
+    let init_status = unsafe {
+        FLAC__stream_decoder_init_file(
+            stream_decoder,
+            input_path_cstring.as_ptr(),
+            Some(decoder_write_callback),
+            Some(decoder_metadata_callback),
+            Some(decoder_error_callback),
+            (&raw mut *state).cast(),
+        )
+    };
+
+    if init_status != FLAC__StreamDecoderInitStatus_FLAC__STREAM_DECODER_INIT_STATUS_OK {
+        unsafe { FLAC__stream_decoder_delete(stream_decoder) };
+        return Err(format!(
+            "Stream Decoder file initialization failed. Status: {:?}",
+            init_status
+        ));
+    }
+
+    let processed =
+        unsafe { FLAC__stream_decoder_process_until_end_of_stream(stream_decoder) } != 0;
+    let decoder_state = unsafe { FLAC__stream_decoder_get_state(stream_decoder) };
+
+    // finish returns false if the md5 of the decoded audio doesn't match the one in STREAMINFO
+    let md5_ok = unsafe { FLAC__stream_decoder_finish(stream_decoder) } != 0;
+    unsafe { FLAC__stream_decoder_delete(stream_decoder) };
+
+    if !strict {
+        if state.audio.sample_rate == 0 {
+            return Err("Failed to decode. No STREAMINFO found".to_string());
         }
+        if !processed || state.error.is_some() {
+            warn!(
+                "Decoding stopped early, recovering what could be read. Decoder state: {} Decoder error status: {:?}",
+                decoder_state, state.error
+            );
+        }
+        return Ok(state.audio);
+    }
+
+    if !processed {
+        return Err(format!(
+            "Failed to decode. Decoder state: {}",
+            decoder_state
+        ));
     }
+    if let Some(status) = state.error {
+        return Err(format!(
+            "Failed to decode. Decoder error status: {}",
+            status
+        ));
+    }
+    if !md5_ok {
+        return Err("Failed to decode. Decoded audio does not match MD5 signature".to_string());
+    }
+
+    Ok(state.audio)
 }
@@ -1,8 +1,14 @@
+use crate::config::AudioFormat;
 use crate::sdl::Gfx;
 use crate::utils::or_die;
 use sdl3_sys::everything::{SDL_FColor, SDL_FRect};
 use std::cmp::{max, min};
 
+const MAX_AMPLITUDE: u32 = (i32::MAX >> 8) as u32; // 24bits
+
+// peaks this loud have almost certainly clipped
+const CLIP_AMPLITUDE: u32 = MAX_AMPLITUDE - 6;
+
 const BACKGROUND_COLOR: SDL_FColor = SDL_FColor {
     r: 0.207,
     g: 0.207,
@@ -67,6 +73,53 @@ const BUTTON_CLICKED_COLOR: SDL_FColor = SDL_FColor {
     a: 1.0,
 };
 
+// the peak of every 10ms of audio for each channel, scaled to 24 bits whatever the bits per sample
+pub struct Waveforms {
+    format: AudioFormat,
+    peaks: Vec<Vec<u32>>,
+    clipped: Vec<bool>, // stays set once a channel clips so it isn't missed while looking away
+    unchunked: Vec<i32>, // interleaved samples that don't make up a whole chunk yet
+}
+
+impl Waveforms {
+    pub fn new(format: AudioFormat) -> Self {
+        Waveforms {
+            format,
+            peaks: vec![Vec::new(); format.channels as usize],
+            clipped: vec![false; format.channels as usize],
+            unchunked: Vec::new(),
+        }
+    }
+
+    // combine audio into chunks for display
+    pub fn append(&mut self, samples: &[i32]) {
+        let channels = self.format.channels as usize;
+        let chunk_size = (self.format.sample_rate / 100).max(1) as usize * channels;
+
+        self.unchunked.extend_from_slice(samples);
+
+        for chunk in self.unchunked.chunks_exact(chunk_size) {
+            for (channel, peaks) in self.peaks.iter_mut().enumerate() {
+                let max_sample = chunk
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .map(|s| s.unsigned_abs())
+                    .max()
+                    .unwrap_or(0);
+                let peak = (max_sample as u64 * MAX_AMPLITUDE as u64
+                    / self.format.max_amplitude() as u64) as u32;
+
+                peaks.push(peak);
+                self.clipped[channel] |= peak >= CLIP_AMPLITUDE;
+            }
+        }
+
+        let chunked = (self.unchunked.len() / chunk_size) * chunk_size;
+        self.unchunked.drain(..chunked);
+    }
+}
+
 // contains the events that occurred this frame
 pub struct UI {
     state: Input,
@@ -204,7 +257,62 @@ impl UI {
         or_die(self.gfx.set_render_scale(1.0, 1.0));
     }
 
-    pub fn draw_waveform(
+    // lines of small text for things the user should know about
+    pub fn draw_notices(&self, notices: &[String], x: f32, y: f32) {
+        const SIZE: f32 = 2.0;
+        const LINE_SIZE: f32 = 8.0 * SIZE + 6.0;
+
+        or_die(self.gfx.set_render_draw_color(TEXT_COLOR));
+
+        for (i, notice) in notices.iter().enumerate() {
+            self.draw_text(notice, x, y + i as f32 * LINE_SIZE, SIZE, false, false);
+        }
+    }
+
+    // one waveform per channel stacked top to bottom, labelled when there are several or once a channel has clipped
+    pub fn draw_waveforms(
+        &self,
+        waveforms: &Waveforms,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        is_recording: bool,
+    ) {
+        const LABEL_SIZE: f32 = 2.0;
+
+        let channels = waveforms.peaks.len();
+        let lane_height = height / channels as f32;
+
+        for (channel, peaks) in waveforms.peaks.iter().enumerate() {
+            let lane_y = y + channel as f32 * lane_height;
+            self.draw_waveform(peaks, x, lane_y, width, lane_height, is_recording);
+
+            let clipped = waveforms.clipped[channel];
+            let label = match (channels > 1, clipped) {
+                (true, true) => format!("Ch {} clipped", channel + 1),
+                (true, false) => format!("Ch {}", channel + 1),
+                (false, true) => String::from("Clipped"),
+                (false, false) => continue,
+            };
+
+            or_die(self.gfx.set_render_draw_color(if clipped {
+                WAVEFORM_CLIPPED_COLOR
+            } else {
+                TEXT_COLOR
+            }));
+            self.draw_text(
+                &label,
+                x + 10.0,
+                lane_y + lane_height - 10.0 - 8.0 * LABEL_SIZE,
+                LABEL_SIZE,
+                false,
+                false,
+            );
+        }
+    }
+
+    fn draw_waveform(
         &self,
         waveform: &[u32],
         x: f32,
@@ -238,7 +346,6 @@ impl UI {
 
         // todo render the new audio to a buffer which can then be scrolled on the screen, rather than line rendering the whole waveform
 
-        const MAX_AMPLITUDE: u32 = (i32::MAX >> 8) as u32; // 24bits
         let max_conversion_factor: f32 = height / MAX_AMPLITUDE as f32;
         let y_middle = y + height / 2.0;
 
@@ -252,7 +359,7 @@ impl UI {
             .enumerate()
         {
             // if clipped, draw as red
-            if *m >= MAX_AMPLITUDE - 6 {
+            if *m >= CLIP_AMPLITUDE {
                 if col > max_col.saturating_sub(20) {
                     // if this is one of the most recent samples
                     is_clipping_now = true;
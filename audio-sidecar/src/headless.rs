@@ -0,0 +1,114 @@
+use crate::config::ProgramConfig;
+use crate::recording::{self, Recording};
+use crate::sdl::{self, Event};
+use crate::signals;
+use log::info;
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+// how long to wait between reading audio, the stream buffers everything in between
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// optional reasons to stop recording on its own
+#[derive(Debug, Default)]
+pub struct Limits {
+    pub duration: Option<Duration>,
+    pub silence_timeout: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    Requested, // Enter or a signal
+    Duration,
+    Silence,
+}
+
+// record until something says to stop
+pub fn record(config: &ProgramConfig, recording: &mut Recording, limits: &Limits) -> Stop {
+    let enter_pressed = watch_stdin();
+
+    let format = recording.interface().format;
+    let duration_samples = limits.duration.map(|d| format.samples(d));
+    let silence_samples = limits.silence_timeout.map(|d| format.samples(d));
+    let silence_threshold = recording::silence_threshold(&format);
+
+    let mut recorded = 0u64; // only what was recorded this time, not what was appended to
+    let mut silent = 0u64;
+
+    info!("Recording. Press Enter to stop.");
+
+    loop {
+        if signals::shutdown_requested() {
+            info!("Received signal to quit");
+            return Stop::Requested;
+        }
+
+        if enter_pressed.try_recv().is_ok() {
+            info!("Enter pressed");
+            return Stop::Requested;
+        }
+
+        // nothing arrives while the interface is unplugged, so the limits wait for it to come back
+        while let Some(event) = sdl::poll_event() {
+            if let Event::ADevice(event_type, e) = event {
+                recording.handle_device_event(event_type, &e);
+            }
+        }
+
+        let mut samples = recording.read(config.shift_gain);
+
+        // cut exactly at the limit so scripted recordings have a predictable length
+        let mut reached_duration = false;
+        if let Some(limit) =
+            duration_samples.filter(|limit| recorded + samples.len() as u64 >= *limit)
+        {
+            samples.truncate((limit - recorded) as usize);
+            reached_duration = true;
+        }
+
+        recording.encode(&samples);
+        recorded += samples.len() as u64;
+
+        match samples
+            .iter()
+            .rposition(|s| s.unsigned_abs() > silence_threshold)
+        {
+            Some(i) => silent = (samples.len() - 1 - i) as u64,
+            None => silent += samples.len() as u64,
+        }
+
+        if reached_duration {
+            info!("Recorded for {:?}", limits.duration.unwrap_or_default());
+            return Stop::Duration;
+        }
+
+        if silence_samples.is_some_and(|limit| silent >= limit) {
+            info!(
+                "Stopping after {:?} of silence",
+                limits.silence_timeout.unwrap_or_default()
+            );
+            return Stop::Silence;
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// a line on stdin means stop. The end of input doesn't, so it's fine to run with stdin closed
+fn watch_stdin() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        if std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .is_ok_and(|n| n > 0)
+        {
+            let _ = sender.send(());
+        }
+    });
+
+    receiver
+}
@@ -2,48 +2,52 @@ extern crate flac_sys;
 extern crate sdl3_sys;
 
 use self::config::ExistingFileStrategy;
-use crate::config::ProgramConfig;
-use crate::flac::Encoder;
-use crate::gui::{Input, UI};
+use crate::cli::Command;
+use crate::config::{InterfacePattern, ProgramConfig};
+use crate::gui::{Input, Waveforms, UI};
+use crate::recording::{DeviceChange, Interface, Recording};
 use crate::sdl::Event;
+use crate::sidecar::SidecarName;
 use crate::utils::die;
-use crate::utils::or_die;
 use log::{debug, error, info, warn};
 use sdl3_sys::everything::*;
 use std::any::Any;
 use std::backtrace::Backtrace;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
-use std::{env, io, panic};
+use std::{env, io, panic, process, thread};
 use tracing::Level;
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{fmt, layer::SubscriberExt};
 
+mod cli;
 mod config;
 mod flac;
 mod gui;
+mod headless;
+mod recording;
+mod recovery;
 mod sdl;
+mod sidecar;
+mod signals;
+mod state;
+mod trash;
 mod utils;
 // todo copious error checking
 // todo save performance stats and/or performance stats outside of normal
 // todo if audio is for an image, load a thumbnail and display it so it's clearer which file the audio will be associated with. Loading thumbnails rather than the image itself should be both faster and have fewer file formats to deal with. We could even try to load _any_ thumbnail that matches the file in question, say for video files, since we'll only care if there _is_ one. See https://askubuntu.com/questions/1368910/how-to-create-custom-thumbnailers-for-nautilus-nemo-and-caja and https://specifications.freedesktop.org/thumbnail-spec/latest/thumbsave.html
 
 // todo add error checking, logging, and dad friendly error reporting
-// todo add safeguards such as not overwriting existing recordings and/or saving old recordings to a backup directory on overwrite
-// todo append new data to file and fixup header when recording to an existing file
-// todo handle multiple paths sent to this program i.e. drop everything after first
 // todo organize better / refactor / split into separate source files
 // todo create a slideshow application that plays the audio with the corresponding picture, advancing to the next once the audio is done. slideshow will play everything in directory
 // todo   add optional "music" for background since he wants to put specific music in the background.
 // todo   slideshow will also display metadata that was entered such as title and comments etc
 // todo add keyboard shortcut to nautilus extension?
 
-// todo periodically check if new audio devices have been added (especially if none of the ideal ones are detected yet), see getaudiorecordingdevices or eventing
 // todo assign flac album cover art to image it was created for with extra audio icon????
 
-// todo try to select the first audio input if multiple matches, or test both inputs to see which has any audio signal and use that one
 // todo display a user facing message about needing to turn the audio interface on/plug in if it isn't detected
 // todo analyze the audio recorded so far and if its max amplitude (when excluding a few outliers??? like loud pops?) is too low, show a message to raise the gain. Similarly, if clipping regularly, show message asking to lower gain
 // todo rearrange code so as much as possible can be tested via test runners
@@ -53,7 +57,24 @@ mod utils;
 // todo BUG scarlett gen 3 audio interface on ubuntu 22.04 does not hit max amplitude (and can't be bit shifted perfectly to max)
 // todo BUG scarlett gen 3 audio interface on ubuntu 22.04 does not start returning audio samples for a second when the program first opens
 // todo add a .desktop file and way to launch program which will ask where to save on launch (since there isn't a cli arg)????
-// todo ensure audio filename isn't too long. adding _audio.flac to the end of a filename at the limit will fail to save. Must truncate at end until file can save
+
+const BORDER_SIZE: f32 = 10.0;
+const CONTROL_HEIGHT: f32 = 100.0 - BORDER_SIZE * 3.0;
+
+// shown until the interface comes back, recording carries on into the same file then
+const DISCONNECTED_NOTICE: &str = "Interface disconnected, plug it back in to keep recording";
+
+// how much audio each device is listened to for when Interface is auto
+const PROBE_DURATION: Duration = Duration::from_millis(500);
+
+// some interfaces take a second or so after opening to deliver any audio, devices still silent by then are skipped
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// how often the devices are read while probing
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// how often to look for the configured interface while recording from the default device
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
 
 // redirect panics to log file. woe is me if this panics within the logger itself
 fn handle_panic(payload: &(dyn Any + Send), backtrace: Backtrace) {
@@ -70,7 +91,29 @@ fn handle_panic(payload: &(dyn Any + Send), backtrace: Backtrace) {
 }
 
 pub fn main() {
-    let config = ProgramConfig::from_file().unwrap();
+    // nothing is logged yet, so problems with the arguments or config go straight to the terminal
+    let args = match cli::parse_args(env::args().skip(1)) {
+        Ok(a) => a,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    match args.command {
+        Command::Record | Command::Narrate | Command::ListDevices => {}
+        Command::Version => return println!("audio_sidecar {}", env!("CARGO_PKG_VERSION")),
+        Command::Help => return println!("{}", cli::USAGE),
+    }
+
+    let mut config = match ProgramConfig::from_file(&args.config_path) {
+        Ok(c) => c,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    };
+    args.apply_to(&mut config);
 
     let log_path = Path::new(&config.log_file);
     let file_appender = tracing_appender::rolling::never(
@@ -90,23 +133,58 @@ pub fn main() {
         );
     subscriber.init();
 
-    panic::set_hook(Box::new(|info| {
+    // the encoders belong to the main thread, another thread panicking doesn't stop it using them
+    let main_thread = thread::current().id();
+    panic::set_hook(Box::new(move |info| {
         let backtrace = Backtrace::force_capture();
-        handle_panic(info.payload(), backtrace)
+        handle_panic(info.payload(), backtrace);
+
+        // whatever went wrong, the recording so far shouldn't be lost with it
+        if thread::current().id() == main_thread {
+            flac::finish_unfinished();
+        } else {
+            warn!("Panic outside the main thread, recording carries on");
+        }
     }));
 
     info!("============= Started =============");
 
-    let args: Vec<String> = env::args().collect();
+    if args.command == Command::ListDevices {
+        return list_devices(&config);
+    }
 
-    let defaultpath = String::from("/tmp/test.png");
-    let filepath = Path::new(args.get(1).unwrap_or(&defaultpath));
-    info!("Audio associated with file: {:?}", filepath);
+    // recorded one after another. The audio is saved for the first file of each entry and shared with the rest
+    let queue: Vec<Vec<PathBuf>> = if args.command == Command::Narrate {
+        narration_queue(&config, &args.filepaths, args.include_recorded)
+    } else {
+        vec![args.filepaths.clone()]
+    };
 
-    if let Err(msg) = sdl::init(SDL_INIT_VIDEO | SDL_INIT_AUDIO | SDL_INIT_EVENTS) {
+    if queue.is_empty() {
+        info!("Every file already has audio, nothing to narrate");
+        return println!(
+            "Every file already has audio, use --include-recorded to narrate them again"
+        );
+    }
+
+    let sdl_flags = if args.headless {
+        SDL_INIT_AUDIO | SDL_INIT_EVENTS
+    } else {
+        SDL_INIT_VIDEO | SDL_INIT_AUDIO | SDL_INIT_EVENTS
+    };
+    if let Err(msg) = sdl::init(sdl_flags) {
         die(format!("SDL initialization failed: {}", msg).as_str());
     }
 
+    // installed after SDL so ours replace the ones it sets up
+    if let Err(msg) = signals::install_handlers() {
+        die(msg.as_str());
+    }
+
+    if args.headless {
+        return record_headless(&config, &args);
+    }
+
     // window inits as x11 instead of wayland due to lack of fifo-v1 protocol in gnome.
     // fifo-v1 was added here https://gitlab.gnome.org/GNOME/mutter/-/merge_requests/3355 and will be present in gnome 48.
     // The X11 window is responsible for the window flashing on creation. Wayland does not experience this issue.
@@ -132,364 +210,1152 @@ pub fn main() {
         die(format!("SDL vsync failed to enable: {}", msg).as_str());
     }
 
+    let mut interface = open_interface(&config);
+
+    let mut ui = UI::new(gfx);
+    let mut input = Input::default();
+
+    for (queue_index, filepaths) in queue.iter().enumerate() {
+        let filepath = filepaths[0].as_path();
+        let is_last = queue_index == queue.len() - 1;
+
+        info!("Audio associated with file: {:?}", filepath);
+        if filepaths.len() > 1 {
+            info!(
+                "Audio will be shared with {} other files: {:?}",
+                filepaths.len() - 1,
+                &filepaths[1..]
+            );
+        }
+
+        let mut waveforms = Waveforms::new(interface.format);
+
+        let mut paused = false;
+
+        // shown over the waveform while recording
+        let mut notices: Vec<String> = Vec::new();
+
+        if queue.len() > 1 {
+            notices.push(format!(
+                "File {} of {}: {}",
+                queue_index + 1,
+                queue.len(),
+                filepath.file_name().unwrap_or_default().to_string_lossy()
+            ));
+        }
+
+        let (sidecar_name, saving_elsewhere) =
+            sidecar_name_for(&config, filepath, &interface.name, &mut notices);
+
+        // the other selected files get the same audio once it's saved
+        let shared_with: Vec<SidecarName> = filepaths[1..]
+            .iter()
+            .map(|filepath| sidecar_name_for(&config, filepath, &interface.name, &mut notices).0)
+            .collect();
+        if !shared_with.is_empty() {
+            notices.push(format!("Recording for {} files", shared_with.len() + 1));
+        }
+
+        let encoder_config = encoder_config(&interface);
+
+        // audio captured before the encoder exists, i.e. while the user decides what to do with an existing sidecar
+        let mut pending_samples: Vec<i32> = Vec::new();
+        let mut capture_pending = |device_events: &[DeviceEvent]| {
+            for (event_type, e) in device_events {
+                match interface.handle_device_event(*event_type, e) {
+                    Some(DeviceChange::Disconnected) => {
+                        notices.push(String::from(DISCONNECTED_NOTICE))
+                    }
+                    Some(DeviceChange::Reconnected) => notices.retain(|n| n != DISCONNECTED_NOTICE),
+                    None => {}
+                }
+            }
+            pending_samples.append(&mut interface.read(config.shift_gain));
+            !interface.is_connected()
+        };
+
+        let chosen = choose_output(
+            &config,
+            &sidecar_name,
+            &encoder_config,
+            Some(&mut |lines: &[&str], choices: &[&str]| {
+                prompt(
+                    &mut ui,
+                    &mut input,
+                    &mut window_width,
+                    &mut window_height,
+                    lines,
+                    choices,
+                    &mut capture_pending,
+                )
+            }),
+        );
+        let (outputfile, mut carried_samples) = match chosen {
+            Some(c) => c,
+            None => return cancel(&ui, &mut interface),
+        };
+
+        // beside the source is where people expect it, otherwise they need to be told where to look
+        if saving_elsewhere {
+            notices.push(format!("Saving to {}", outputfile.display()));
+        }
+
+        // the encoder starts a new file that replaces the sidecar when saved, so the previous audio must be written before anything new
+        carried_samples.append(&mut pending_samples);
+        let outputfiles = output_files(&config, &interface, &sidecar_name, outputfile);
+        let mut recording = start_recording(&outputfiles, &mut interface, &carried_samples);
+
+        // show the user they are continuing an existing recording
+        waveforms.append(&carried_samples);
+
+        // the configured interface may be switched on after starting. Until there's sound it's switched to straight
+        // away, after that the user decides since the recording would change sound partway through
+        let mut rescan_time = Instant::now();
+        let mut device_added = false;
+        let mut captured_sound = false;
+        let silence_threshold = recording::silence_threshold(&recording.interface().format);
+        let mut available: Option<sdl::AudioDevice> = None;
+
+        let mut frame_time = Instant::now();
+        let mut max_time = Instant::now();
+        let mut max_frame_time = 0.0;
+
+        let mut frames = 0;
+        let mut framespersec = 0.0;
+        let mut start_sec = Instant::now();
+
+        loop {
+            // poll until all events are handled and the queue runs dry
+            while let Some(event) = sdl::poll_event() {
+                match event {
+                    Event::Quit(_) => {
+                        return save_and_quit(&config, &ui, recording, &shared_with);
+                    }
+                    Event::ADevice(event_type, e) => {
+                        device_added |= event_type == SDL_EventType::AUDIO_DEVICE_ADDED;
+
+                        match recording.handle_device_event(event_type, &e) {
+                            Some(DeviceChange::Disconnected) => {
+                                notices.push(String::from(DISCONNECTED_NOTICE))
+                            }
+                            Some(DeviceChange::Reconnected) => {
+                                notices.retain(|n| n != DISCONNECTED_NOTICE)
+                            }
+                            None => {}
+                        }
+                    }
+                    event => {
+                        handle_ui_event(&event, &mut input, &mut window_width, &mut window_height)
+                    }
+                }
+            }
+
+            if signals::shutdown_requested() {
+                info!("Received signal to quit");
+                return save_and_quit(&config, &ui, recording, &shared_with);
+            }
+
+            ui.apply_input(&input);
+
+            let samples = recording.read(config.shift_gain);
+
+            if !paused {
+                recording.encode(&samples);
+                captured_sound |= samples.iter().any(|s| s.unsigned_abs() > silence_threshold);
+
+                waveforms.append(&samples);
+            }
+
+            if recording.uses_default_interface()
+                && available.is_none()
+                && (device_added || rescan_time.elapsed() > RESCAN_INTERVAL)
+            {
+                device_added = false;
+                rescan_time = Instant::now();
+
+                if let Some(device) = preferred_device(&config) {
+                    if captured_sound {
+                        notices.push(format!(
+                            "{} is available, press Switch to use it",
+                            device.name
+                        ));
+                        available = Some(device);
+                    } else {
+                        switch_interface(&mut recording, device, &mut notices);
+                    }
+                }
+            }
+
+            ui.clear();
+
+            ui.draw_waveforms(
+                &waveforms,
+                BORDER_SIZE,
+                BORDER_SIZE,
+                window_width as f32 - BORDER_SIZE * 2.0,
+                window_height as f32 - 100.0,
+                !paused && recording.is_connected(),
+            );
+
+            ui.draw_notices(&notices, BORDER_SIZE * 2.0, BORDER_SIZE * 2.0);
+
+            let control_start_y = window_height as f32 - CONTROL_HEIGHT - BORDER_SIZE;
+
+            let p_button_width = 180.0;
+            if ui.button(
+                if paused { "Record" } else { "Pause" },
+                BORDER_SIZE,
+                control_start_y,
+                p_button_width,
+                CONTROL_HEIGHT,
+            ) {
+                info!("pressed play/pause audio button");
+                paused = !paused;
+            }
+
+            ui.draw_text(
+                format!(
+                    "Record Time: {}",
+                    utils::format_duration(Duration::from_secs_f64(recording.seconds()))
+                )
+                .as_str(),
+                BORDER_SIZE + p_button_width + BORDER_SIZE * 2.0,
+                control_start_y + (CONTROL_HEIGHT / 2.0),
+                3.0,
+                false,
+                true,
+            );
+
+            let switch_width = 180.0;
+            if available.is_some()
+                && ui.button(
+                    "Switch",
+                    window_width as f32 - BORDER_SIZE * 2.0 - switch_width,
+                    BORDER_SIZE * 2.0,
+                    switch_width,
+                    CONTROL_HEIGHT,
+                )
+            {
+                info!("pressed switch interface button");
+                if let Some(device) = available.take() {
+                    notices.retain(|n| !n.ends_with("press Switch to use it"));
+                    switch_interface(&mut recording, device, &mut notices);
+                }
+            }
+
+            let button_width = 180.0;
+            let button_height = 100.0 - BORDER_SIZE * 3.0;
+            if ui.button(
+                if is_last { "Done" } else { "Next" },
+                window_width as f32 - BORDER_SIZE - button_width,
+                control_start_y,
+                button_width,
+                button_height,
+            ) {
+                info!("pressed save audio button");
+
+                if is_last {
+                    return save_and_quit(&config, &ui, recording, &shared_with);
+                }
+
+                // the interface stays open and keeps recording into the next file's sidecar
+                remember_interface(&config, &recording);
+                let saved = recording.save(config.shift_gain);
+                share_recording(&config, &saved, &shared_with);
+                break;
+            }
+
+            // todo do a quick fade between paused sections of audio
+
+            if max_time.elapsed().as_secs_f64() > 5.0 {
+                max_frame_time = 0.0;
+                max_time = Instant::now();
+            }
+
+            let elapsed = frame_time.elapsed().as_secs_f64();
+            if elapsed > max_frame_time {
+                max_frame_time = elapsed;
+            }
+
+            // // todo toggle debug text on and off through config file
+            // let mut debug_text = format!("frametime: {:.2}ms\n", max_frame_time * 1000.0);
+            // debug_text += format!("fps: {}\n", framespersec).as_str();
+            // debug_text += format!("samples: {}\n", sample_count).as_str();
+            // debug_text += format!(
+            //     "data size: {:.1}MiB\n",
+            //     sample_count as f64 * 4.0 / 1024.0 / 1024.0
+            // )
+            // .as_str();
+            // debug_text += format!(
+            //     "waveform size: {:.1}MiB\n",
+            //     display_waveform.len() as f64 * 4.0 / 1024.0 / 1024.0
+            // )
+            // .as_str();
+            // ui.debug_view(debug_text.as_str());
+
+            ui.present();
+            frames += 1;
+            frame_time = Instant::now();
+
+            if start_sec.elapsed().as_secs_f64() > 1.0 {
+                start_sec = Instant::now();
+                framespersec = frames as f64;
+                frames = 0;
+            }
+        }
+    }
+}
+
+fn save_and_quit(
+    config: &ProgramConfig,
+    ui: &UI,
+    recording: Recording,
+    shared_with: &[SidecarName],
+) {
+    remember_interface(config, &recording);
+    let saved = recording.finish(config.shift_gain, true);
+    share_recording(config, &saved, shared_with);
+
+    ui.hide();
+
+    let success_sound = match sdl::loadwav("success.wav") {
+        Ok(a) => a,
+        Err(msg) => die(format!("SDL Failed to load wav: {}", msg).as_str()),
+    };
+
+    debug!("Playing success sound...");
+    sdl::play_sound(&success_sound); // todo make configurable
+
+    sdl::quit();
+
+    info!("============= Exited =============");
+
+    // exit(0); // todo avoid exiting the program with exit() to allow things to drop, etc.
+}
+
+// give each of the other selected files a sidecar with the same audio
+fn share_recording(config: &ProgramConfig, saved: &[PathBuf], shared_with: &[SidecarName]) {
+    for sidecar_name in shared_with {
+        for saved in saved {
+            // never disturb existing audio of the other files, they just get the next free name
+            let target = sidecar_name.next_free();
+            match sidecar::share(saved, &target, config.shared_audio) {
+                Ok(()) => info!("Shared audio with \"{}\"", target.display()),
+                Err(msg) => error!("Could not share audio: {}", msg),
+            }
+        }
+    }
+}
+
+// record without a window until stopped by Enter, a signal, the duration limit or silence
+fn record_headless(config: &ProgramConfig, args: &cli::Args) {
+    let mut interface = open_interface(config);
+
+    // there is nowhere to show notices, they are in the log already
+    let (sidecar_name, _) =
+        sidecar_name_for(config, &args.filepaths[0], &interface.name, &mut Vec::new());
+    let shared_with: Vec<SidecarName> = args.filepaths[1..]
+        .iter()
+        .map(|filepath| sidecar_name_for(config, filepath, &interface.name, &mut Vec::new()).0)
+        .collect();
+
+    let encoder_config = encoder_config(&interface);
+
+    let (outputfile, carried_samples) =
+        match choose_output(config, &sidecar_name, &encoder_config, None) {
+            Some(c) => c,
+            None => unreachable!("nothing is cancelled without asking"),
+        };
+
+    let outputfiles = output_files(config, &interface, &sidecar_name, outputfile);
+    let mut recording = start_recording(&outputfiles, &mut interface, &carried_samples);
+
+    let stop = headless::record(config, &mut recording, &args.limits);
+
+    // the limit was reached exactly, anything after it wasn't asked for
+    remember_interface(config, &recording);
+    let saved = recording.finish(config.shift_gain, stop != headless::Stop::Duration);
+    share_recording(config, &saved, &shared_with);
+
+    sdl::quit();
+
+    info!("============= Exited =============");
+}
+
+// print the interfaces that can record and which one would be used, for finding what to put in Interface or --device
+fn list_devices(config: &ProgramConfig) {
+    if let Err(msg) = sdl::init(SDL_INIT_AUDIO) {
+        die(format!("SDL initialization failed: {}", msg).as_str());
+    }
+
     let recording_devices = match sdl::get_audio_recording_devices() {
         Ok(a) => a,
         Err(msg) => die(format!("SDL finding audio recording devices failed: {}", msg).as_str()),
     };
 
-    let mut desired_interface_id = SDL_AUDIO_DEVICE_DEFAULT_RECORDING;
+    let remembered = remembered_device(config, &recording_devices).map(|d| d.id);
+    let selected =
+        remembered.or_else(|| find_interface(config, &recording_devices, true).map(|d| d.id));
 
-    info!(
-        "Found {} Audio Devices:    (Matching on \"{}\")",
-        recording_devices.len(),
-        config.interface
+    println!(
+        "{:>5}  {:<40}  {:<6}  {:>6}  {:>8}  {:>6}",
+        "ID", "Name", "Format", "Rate", "Channels", "Buffer"
     );
 
-    for device in recording_devices {
-        let found = if device
-            .name
-            .to_lowercase()
-            .contains(config.interface.as_str())
-        {
-            desired_interface_id = device.id;
-            " <<<< MATCH FOUND <<<<"
+    for device in recording_devices.iter() {
+        let format = match sdl::get_audio_device_format(device.id) {
+            Ok(f) => format!(
+                "{:<6}  {:>6}  {:>8}  {:>6}",
+                f.format, f.sample_rate, f.channels, f.buffer_frames
+            ),
+            Err(msg) => format!("unknown format: {}", msg),
+        };
+        let found = if remembered == Some(device.id) {
+            String::from("  <<<< recorded with last time")
+        } else if selected == Some(device.id) {
+            format!("  <<<< selected by Interface = {}", config.interface)
         } else {
-            ""
+            String::new()
         };
 
-        info!("\t{} {}", device.name, found);
+        println!("{:>5}  {:<40}  {}{}", device.id, device.name, format, found);
     }
 
-    if desired_interface_id == SDL_AUDIO_DEVICE_DEFAULT_RECORDING {
-        warn!("No interface match found. Using sdl default recording device")
+    if selected.is_none() && config.interface.is_auto() {
+        println!("\nNo device is receiving sound, the default recording device would be used");
+    } else if selected.is_none() {
+        println!(
+            "\nNo device matches Interface = {}, the default recording device would be used",
+            config.interface
+        );
     }
 
-    let logical_interface_id = match sdl::open_audio_device(desired_interface_id) {
-        Ok(i) => i,
-        Err(msg) => die(format!("SDL could not open audio device: {}", msg).as_str()),
-    };
+    sdl::quit();
+}
+
+// the device that last recorded sound, while Interface is still what it was picked with. Of several with its name the
+// one in the same format is most likely the same device
+fn remembered_device<'a>(
+    config: &ProgramConfig,
+    devices: &'a [sdl::AudioDevice],
+) -> Option<&'a sdl::AudioDevice> {
+    let last = state::LastDevice::load()?;
+
+    if last.interface != config.interface.to_string() {
+        info!(
+            "Not using \"{}\" from last time, Interface has changed since",
+            last.name
+        );
+        return None;
+    }
+
+    // a device ranked higher that's available now still wins. Auto has to listen to know, so it's left to decide
+    let patterns = config.interface.patterns();
+    let outranked = patterns.iter().take(last.rank).any(|pattern| {
+        matches!(pattern, InterfacePattern::Auto)
+            || devices.iter().any(|device| pattern.matches(&device.name))
+    });
+    if outranked {
+        info!(
+            "Not using \"{}\" from last time, Interface prefers another device",
+            last.name
+        );
+        return None;
+    }
 
-    let audio_stream = match sdl::create_audio_stream() {
-        Ok(s) => s,
-        Err(msg) => die(format!("SDL could not create audio stream: {}", msg).as_str()),
+    let same_format = |device: &sdl::AudioDevice| {
+        sdl::get_audio_device_format(device.id).is_ok_and(|f| {
+            f.format == last.format
+                && f.sample_rate == last.sample_rate
+                && f.channels == last.channels
+        })
     };
+    let named: Vec<&sdl::AudioDevice> = devices.iter().filter(|d| d.name == last.name).collect();
+    let device = named
+        .iter()
+        .rev()
+        .find(|device| same_format(device))
+        .or(named.last())
+        .copied();
+
+    match device {
+        Some(device) => info!("Using \"{}\" as it recorded last time", device.name),
+        None => debug!("\"{}\" from last time isn't available", last.name),
+    }
+
+    device
+}
 
-    if let Err(msg) = sdl::bind_audio_stream(logical_interface_id, audio_stream) {
-        die(format!("SDL could not bind logical audio device to stream: {}", msg).as_str());
+// remember a working interface so it's picked first next time
+fn remember_interface(config: &ProgramConfig, recording: &Recording) {
+    let interface = recording.interface();
+    if !recording.heard_sound() || interface.is_default() {
+        return;
     }
 
-    let mut display_waveform: Vec<u32> = Vec::new();
-    let mut previous_unchunked_samples: Vec<i32> = Vec::new();
+    // as it was before opening, which can change what SDL reports
+    let format = interface.device_format();
+    let last = state::LastDevice {
+        name: interface.name.clone(),
+        interface: config.interface.to_string(),
+        rank: pattern_rank(config, &interface.name),
+        format: format.map(|f| f.format.clone()).unwrap_or_default(),
+        sample_rate: format.map(|f| f.sample_rate).unwrap_or_default(),
+        channels: format.map(|f| f.channels).unwrap_or_default(),
+    };
 
-    let mut clicking = false;
-    let mut mouse_x: f32 = 0.0;
-    let mut mouse_y: f32 = 0.0;
-    let mut paused = false;
+    if let Err(msg) = last.save() {
+        warn!("Could not remember the interface: {}", msg);
+    }
+}
 
-    let mut sample_count = 0u64;
-    let mut max_sample_amplitude = 0u32;
+// which of the Interface patterns picked a device by that name, counting from 0. The first pattern matching the name is
+// used even if an auto before it did the picking, so a device is never credited to a better pattern than its own
+fn pattern_rank(config: &ProgramConfig, name: &str) -> usize {
+    let patterns = config.interface.patterns();
+    patterns
+        .iter()
+        .position(|pattern| pattern.matches(name))
+        .or_else(|| {
+            patterns
+                .iter()
+                .position(|pattern| matches!(pattern, InterfacePattern::Auto))
+        })
+        .unwrap_or(patterns.len())
+}
 
-    let filename_base = filepath
-        .with_extension("")
-        .file_name()
-        .expect("filename should be non-empty")
-        .to_string_lossy()
-        .to_string();
-    let filename = filename_base.clone() + "_audio.flac";
+// the device the Interface setting picks, from the first of its patterns that matches one. Auto is skipped unless
+// probe is set since it means listening to every device
+fn find_interface<'a>(
+    config: &ProgramConfig,
+    devices: &'a [sdl::AudioDevice],
+    probe: bool,
+) -> Option<&'a sdl::AudioDevice> {
+    let patterns = config.interface.patterns();
+
+    for (rank, pattern) in patterns.iter().enumerate() {
+        let found = match pattern {
+            InterfacePattern::Auto if !probe => continue,
+            InterfacePattern::Auto => probe_interfaces(config, devices),
+            // the last one is the most recently connected
+            pattern => devices
+                .iter()
+                .rev()
+                .find(|device| pattern.matches(&device.name)),
+        };
 
-    let mut outputfile = filepath.with_file_name(filename);
+        match found {
+            Some(device) => {
+                info!(
+                    "Interface pattern {} of {}, {}, picked \"{}\"",
+                    rank + 1,
+                    patterns.len(),
+                    pattern,
+                    device.name
+                );
+                return Some(device);
+            }
+            // rescans happen every few seconds, which would flood the log
+            None if probe => debug!(
+                "Interface pattern {} of {}, {}, matched nothing",
+                rank + 1,
+                patterns.len(),
+                pattern
+            ),
+            None => {}
+        }
+    }
 
-    if std::fs::exists(&outputfile).unwrap_or(true) {
-        // fail safely, assume conflict if can't determine
-        // todo handle exists() result io failure and logging explicitly
-        info!("File exists at \"{}\"", outputfile.display());
+    None
+}
 
-        match config.existing_file_strategy {
-            ExistingFileStrategy::RenameToLast => {
-                let mut n = 1;
-                let mut tries = 100; //give up after 100 tries to avoid infinite loop
-                while std::fs::exists(&outputfile).unwrap_or(true) {
-                    n += 1;
-                    tries -= 1;
-                    outputfile = filepath.with_file_name(
-                        filename_base.clone() + format!("_audio{}.flac", n).as_str(),
-                    );
-                    if tries <= 0 {
-                        error!("Failed to find next file name in 100 tries. Continuing.");
-                        break;
-                    }
+// listen to every device at once and pick the loudest one that has any sound, since an interface can have several
+// inputs with only some of them plugged in
+fn probe_interfaces<'a>(
+    config: &ProgramConfig,
+    devices: &'a [sdl::AudioDevice],
+) -> Option<&'a sdl::AudioDevice> {
+    let candidates: Vec<(&sdl::AudioDevice, Interface)> = devices
+        .iter()
+        .filter_map(|device| {
+            match Interface::open(device.id, device.name.clone(), &config.audio_format) {
+                Ok(interface) => Some((device, interface)),
+                Err(msg) => {
+                    warn!("Could not listen to \"{}\": {}", device.name, msg);
+                    None
                 }
             }
-            ExistingFileStrategy::Replace => {
-                // do nothing, the file will be replaced
-                // todo, move to trash or something first
-            }
-            _ => todo!(),
+        })
+        .collect();
+
+    // wait until every device has delivered enough audio to judge it by, rather than for a fixed time
+    let mut captured: Vec<Vec<i32>> = vec![Vec::new(); candidates.len()];
+    let start = Instant::now();
+    loop {
+        thread::sleep(PROBE_POLL_INTERVAL);
+
+        for ((_, interface), samples) in candidates.iter().zip(captured.iter_mut()) {
+            samples.append(&mut interface.read(config.shift_gain));
+        }
+
+        let all_heard = candidates
+            .iter()
+            .zip(&captured)
+            .all(|((_, interface), samples)| {
+                let format = &interface.format;
+                let wanted = format.sample_rate as f64
+                    * format.channels as f64
+                    * PROBE_DURATION.as_secs_f64();
+                samples.len() as f64 >= wanted
+            });
+        if all_heard || start.elapsed() >= PROBE_TIMEOUT {
+            break;
         }
     }
 
-    info!("Saving audio to \"{}\"", outputfile.display());
+    info!("Listening for the interface receiving sound:");
 
-    let mut encoder_config = flac::EncoderConfig::new();
+    let mut loudest: Option<(&sdl::AudioDevice, f64)> = None;
+    for ((device, interface), samples) in candidates.into_iter().zip(captured) {
+        let format = &interface.format;
 
-    encoder_config.set_output_path(&outputfile);
+        let (rms, peak) = recording::levels(&samples);
+        info!(
+            "\t{}  RMS: {:.1} dBFS  Peak: {:.1} dBFS",
+            device.name,
+            recording::dbfs(rms, format),
+            recording::dbfs(peak as f64, format)
+        );
 
-    let encoder = match encoder_config.get_encoder() {
-        Ok(e) => e,
-        Err(msg) => die(msg.as_str()),
+        if peak > recording::silence_threshold(format)
+            && loudest.is_none_or(|(_, loudest_rms)| rms > loudest_rms)
+        {
+            loudest = Some((device, rms));
+        }
+        interface.discard();
+    }
+
+    match loudest {
+        Some((device, _)) => info!("Chose \"{}\" as it's the loudest", device.name),
+        None => warn!("None of the interfaces are receiving sound"),
+    }
+
+    loudest.map(|(device, _)| device)
+}
+
+// the configured interface, if it can be found now
+fn preferred_device(config: &ProgramConfig) -> Option<sdl::AudioDevice> {
+    // listening to every device while recording would be disruptive, and none had sound when it was tried
+    let recording_devices = sdl::get_audio_recording_devices().ok()?;
+    find_interface(config, &recording_devices, false).cloned()
+}
+
+fn switch_interface(
+    recording: &mut Recording,
+    device: sdl::AudioDevice,
+    notices: &mut Vec<String>,
+) {
+    match recording.switch_interface(device.id, device.name.clone()) {
+        Ok(()) => notices.push(format!("Recording from {}", device.name)),
+        Err(msg) => error!("Could not switch to \"{}\": {}", device.name, msg),
+    }
+}
+
+// open the configured audio interface, or the default one if it isn't found
+fn open_interface(config: &ProgramConfig) -> Interface {
+    let recording_devices = match sdl::get_audio_recording_devices() {
+        Ok(a) => a,
+        Err(msg) => die(format!("SDL finding audio recording devices failed: {}", msg).as_str()),
     };
 
-    let mut frame_time = Instant::now();
-    let mut max_time = Instant::now();
-    let mut max_frame_time = 0.0;
+    info!(
+        "Found {} Audio Devices:    (Matching on {})",
+        recording_devices.len(),
+        config.interface
+    );
+
+    let selected = remembered_device(config, &recording_devices)
+        .or_else(|| find_interface(config, &recording_devices, true));
 
-    let mut frames = 0;
-    let mut framespersec = 0.0;
-    let mut start_sec = Instant::now();
+    for device in recording_devices.iter() {
+        let found = if selected.is_some_and(|d| d.id == device.id) {
+            " <<<< MATCH FOUND <<<<"
+        } else {
+            ""
+        };
 
-    let mut ui = UI::new(gfx);
-    let mut input = Input::default();
+        info!("\t{} {}", device.name, found);
+    }
 
-    loop {
-        // poll until all events are handled and the queue runs dry
-        while let Some(event) = sdl::poll_event() {
-            match event {
-                // todo New events will have to be added both here and in sdl::poll_event()
-                Event::Window(event_type, e) => {
-                    if event_type == SDL_EventType::WINDOW_RESIZED {
-                        window_width = e.data1 as u32;
-                        window_height = e.data2 as u32;
-                    }
-                }
-                Event::Button(event_type, _e) => {
-                    if event_type == SDL_EventType::MOUSE_BUTTON_DOWN {
-                        clicking = true;
-                    } else if event_type == SDL_EventType::MOUSE_BUTTON_UP {
-                        clicking = false;
-                    }
-                    input.mouse_button_pressed = clicking;
-                }
-                Event::Motion(event_type, e) => {
-                    if event_type == SDL_EventType::MOUSE_MOTION {
-                        mouse_x = e.x;
-                        mouse_y = e.y;
-                    }
-                    input.mouse_x = mouse_x;
-                    input.mouse_y = mouse_y;
-                }
-                Event::Quit(_) => {
-                    return save_and_quit(
-                        &config,
-                        &ui,
-                        encoder,
-                        logical_interface_id,
-                        audio_stream,
-                        sample_count,
-                        max_sample_amplitude,
-                    );
-                }
-                _ => continue,
-            }
+    let (desired_interface_id, desired_interface_name) = match selected {
+        Some(device) => (device.id, device.name.clone()),
+        None => {
+            warn!("No interface match found. Using sdl default recording device");
+            (SDL_AUDIO_DEVICE_DEFAULT_RECORDING, String::from("default"))
         }
+    };
 
-        ui.apply_input(&input);
+    match Interface::open(
+        desired_interface_id,
+        desired_interface_name,
+        &config.audio_format,
+    ) {
+        Ok(i) => i,
+        Err(msg) => die(msg.as_str()),
+    }
+}
 
-        let mut samples = match sdl::get_audio_stream_data_i32(audio_stream, config.shift_gain) {
-            Ok(s) => s,
-            Err(msg) => die(format!("SDL GetAudioStreamData failed: {}", msg).as_str()),
+// the files to narrate in order, with the files in directories listed by name. Files that already have audio are left
+// out unless include_recorded is set, and so are sidecars themselves
+fn narration_queue(
+    config: &ProgramConfig,
+    paths: &[PathBuf],
+    include_recorded: bool,
+) -> Vec<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut dir_files: Vec<PathBuf> = match std::fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_ok_and(|t| !t.is_dir()))
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                .map(|entry| entry.path())
+                .collect(),
+            Err(e) => die(format!("Could not list \"{}\": {}", path.display(), e).as_str()),
         };
+        dir_files.sort();
+        files.append(&mut dir_files);
+    }
 
-        if !paused {
-            sample_count += samples.len() as u64;
-            for s in samples.iter() {
-                if s.saturating_abs() as u32 > max_sample_amplitude {
-                    max_sample_amplitude = s.saturating_abs() as u32;
-                }
+    let sidecar_names: Vec<SidecarName> = files
+        .iter()
+        .map(|filepath| {
+            let dir = match sidecar::output_dir(filepath, config.output_directory.as_deref()) {
+                Ok((dir, _)) => dir,
+                Err(msg) => die(format!("Could not find a place to save audio: {}", msg).as_str()),
+            };
+            // the device isn't known yet, it doesn't matter for finding sidecars
+            SidecarName::new(filepath, &dir, &config.filename_template, "")
+        })
+        .collect();
+
+    files
+        .iter()
+        .zip(sidecar_names.iter())
+        .filter(|(filepath, _)| !sidecar_names.iter().any(|name| name.is_sidecar(filepath)))
+        .filter(|(filepath, sidecar_name)| {
+            let recorded = sidecar_name.has_sidecar();
+            if recorded && !include_recorded {
+                info!(
+                    "Skipping \"{}\" since it already has audio",
+                    filepath.display()
+                );
             }
+            include_recorded || !recorded
+        })
+        .map(|(filepath, _)| vec![filepath.clone()])
+        .collect()
+}
 
-            or_die(encoder.encode(&samples)); // encode and save to file as we go
+// decide where sidecars go and how they are named, adding anything the user should know to notices
+// also returns whether the sidecars are saved somewhere other than beside the source
+fn sidecar_name_for(
+    config: &ProgramConfig,
+    filepath: &Path,
+    interface_name: &str,
+    notices: &mut Vec<String>,
+) -> (SidecarName, bool) {
+    let (output_dir, is_fallback_dir) =
+        match sidecar::output_dir(filepath, config.output_directory.as_deref()) {
+            Ok(d) => d,
+            Err(msg) => die(format!("Could not find a place to save audio: {}", msg).as_str()),
+        };
 
-            // combine audio into chunks for display
-            const CHUNKSIZE: usize = 44100 / 100; // samples
+    let sidecar_name = SidecarName::new(
+        filepath,
+        &output_dir,
+        &config.filename_template,
+        interface_name,
+    );
 
-            previous_unchunked_samples.append(&mut samples);
-            let mut max_sample = 0;
-            for n in 0..previous_unchunked_samples.len() / CHUNKSIZE {
-                for i in 0..CHUNKSIZE {
-                    let v = previous_unchunked_samples[n * CHUNKSIZE + i].unsigned_abs();
-                    if v > max_sample {
-                        max_sample = v;
-                    }
-                }
-                display_waveform.push(max_sample);
-                max_sample = 0;
-            }
-            previous_unchunked_samples = previous_unchunked_samples
+    // FilenameTemplate rules out most of these, but a template like {stem}._audio still names the sidecar of
+    // photo._audio after the photo itself
+    let first = sidecar_name.path(1);
+    let is_source = first == filepath
+        || first
+            .canonicalize()
+            .is_ok_and(|first| filepath.canonicalize().is_ok_and(|source| source == first));
+    if is_source {
+        die(format!(
+            "The sidecar of \"{}\" would be the file itself, change FilenameTemplate",
+            filepath.display()
+        )
+        .as_str());
+    }
+
+    if is_fallback_dir {
+        notices.push(String::from(
+            "Can't save in the usual folder, it may be read only",
+        ));
+    }
+
+    if !config.filename_template.includes_extension() {
+        let siblings = sidecar::same_stem_siblings(filepath);
+        if !siblings.is_empty() {
+            let names: Vec<String> = siblings
                 .iter()
-                .skip((previous_unchunked_samples.len() / CHUNKSIZE) * CHUNKSIZE)
-                .cloned()
+                .map(|p| {
+                    p.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string()
+                })
                 .collect();
+            warn!(
+                "Audio for this file has the same name as audio for {}. Set SidecarNaming = \"with-extension\" or add {{ext}} to FilenameTemplate to tell them apart.",
+                names.join(", ")
+            );
+            notices.push(format!("Audio name is shared with {}", names.join(", ")));
         }
+    }
 
-        ui.clear();
+    (
+        sidecar_name,
+        config.output_directory.is_some() || is_fallback_dir,
+    )
+}
 
-        const BORDER_SIZE: f32 = 10.0;
+// shows lines of text and returns the index of the chosen choice, see prompt
+type Ask<'a> = &'a mut dyn FnMut(&[&str], &[&str]) -> Option<usize>;
 
-        ui.draw_waveform(
-            &display_waveform,
-            BORDER_SIZE,
-            BORDER_SIZE,
-            window_width as f32 - BORDER_SIZE * 2.0,
-            window_height as f32 - 100.0,
-            !paused,
-        );
+// deal with unfinished recordings and an existing sidecar, asking the user what to do if ask is given
+// returns the sidecar to record to and the audio that must be written before anything new, or None if the user cancelled
+fn choose_output(
+    config: &ProgramConfig,
+    sidecar_name: &SidecarName,
+    encoder_config: &flac::EncoderConfig,
+    mut ask: Option<Ask>,
+) -> Option<(PathBuf, Vec<i32>)> {
+    let mut outputfile = sidecar_name.path(1);
+
+    // audio to encode before anything new. When appending, this starts with the audio already in the sidecar
+    let mut carried_samples: Vec<i32> = Vec::new();
+
+    // recordings that were interrupted by a crash or power loss. What's recovered from them isn't the existing audio
+    // the strategy is meant for, so it's never replaced or appended to
+    let mut recovered_files: Vec<PathBuf> = Vec::new();
+    for orphan in recovery::find_orphans(sidecar_name) {
+        let choice = match ask.as_deref_mut() {
+            Some(ask) => ask(
+                &[
+                    "Found an unsaved recording from last time",
+                    format!(
+                        "{} ({})",
+                        orphan
+                            .path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        utils::format_duration(orphan.duration())
+                    )
+                    .as_str(),
+                ],
+                &["Keep", "Discard"],
+            ),
+            // nobody to ask, so don't lose anything
+            None => Some(0),
+        };
 
-        const CONTROL_HEIGHT: f32 = 100.0 - BORDER_SIZE * 3.0;
-        let control_start_y = window_height as f32 - CONTROL_HEIGHT - BORDER_SIZE;
-
-        let p_button_width = 180.0;
-        if ui.button(
-            if paused { "Record" } else { "Pause" },
-            BORDER_SIZE,
-            control_start_y,
-            p_button_width,
-            CONTROL_HEIGHT,
-        ) {
-            info!("pressed play/pause audio button");
-            paused = !paused;
+        match choice {
+            Some(0) => match recovery::keep(orphan, sidecar_name) {
+                Ok(recovered) => {
+                    info!("Recovered audio saved to \"{}\"", recovered.display());
+                    recovered_files.push(recovered);
+                }
+                Err(msg) => error!("Could not save recovered audio: {}", msg),
+            },
+            Some(_) => match trash::discard(&orphan.path, config.backup_directory.as_deref()) {
+                Ok(discarded) => info!("Moved unfinished recording to \"{}\"", discarded.display()),
+                Err(msg) => error!("Could not discard unfinished recording: {}", msg),
+            },
+            None => return None,
         }
+    }
 
-        ui.draw_text(
-            format!(
-                "Record Time: {}",
-                utils::format_duration(Duration::from_secs_f64(sample_count as f64 / 44100.0,))
-            )
-            .as_str(),
-            BORDER_SIZE + p_button_width + BORDER_SIZE * 2.0,
-            control_start_y + (CONTROL_HEIGHT / 2.0),
-            3.0,
-            false,
-            true,
+    if recovered_files.contains(&outputfile) {
+        outputfile = sidecar_name.next_free();
+        info!(
+            "Recording to \"{}\" beside the recovered audio",
+            outputfile.display()
         );
+    } else if std::fs::exists(&outputfile).unwrap_or(true) {
+        // fail safely, assume conflict if can't determine
+        // todo handle exists() result io failure and logging explicitly
+        info!("File exists at \"{}\"", outputfile.display());
 
-        let button_width = 180.0;
-        let button_height = 100.0 - BORDER_SIZE * 3.0;
-        if ui.button(
-            "Done",
-            window_width as f32 - BORDER_SIZE - button_width,
-            control_start_y,
-            button_width,
-            button_height,
-        ) {
-            info!("pressed save audio button");
-
-            return save_and_quit(
-                &config,
-                &ui,
-                encoder,
-                logical_interface_id,
-                audio_stream,
-                sample_count,
-                max_sample_amplitude,
-            );
+        let mut strategy = config.existing_file_strategy;
+
+        if strategy == ExistingFileStrategy::Ask {
+            const CHOICES: [(&str, Option<ExistingFileStrategy>); 4] = [
+                ("Append", Some(ExistingFileStrategy::Append)),
+                ("Keep both", Some(ExistingFileStrategy::RenameToLast)),
+                ("Replace", Some(ExistingFileStrategy::Replace)),
+                ("Cancel", None),
+            ];
+
+            let choice = match ask {
+                Some(ask) => ask(
+                    &[
+                        "Audio already exists for this file",
+                        &outputfile.file_name().unwrap_or_default().to_string_lossy(),
+                    ],
+                    &CHOICES.map(|(label, _)| label),
+                ),
+                // the safest choice when nobody can be asked
+                None => Some(1),
+            };
+
+            match choice.and_then(|i| CHOICES[i].1) {
+                Some(chosen) => {
+                    info!("User chose {:?} for the existing file", chosen);
+                    strategy = chosen;
+                }
+                None => return None,
+            }
         }
 
-        // todo do a quick fade between paused sections of audio
-
-        if max_time.elapsed().as_secs_f64() > 5.0 {
-            max_frame_time = 0.0;
-            max_time = Instant::now();
+        match strategy {
+            ExistingFileStrategy::RenameToLast => {
+                outputfile = sidecar_name.next_free();
+            }
+            ExistingFileStrategy::RenameToFirst => {
+                if let Err(msg) = sidecar_name.make_first_free() {
+                    // whatever was renamed so far is intact, so just record under the next free name
+                    error!("{}. Saving to a new file instead.", msg);
+                    outputfile = sidecar_name.next_free();
+                }
+            }
+            ExistingFileStrategy::Replace => {
+                match trash::discard(&outputfile, config.backup_directory.as_deref()) {
+                    Ok(discarded) => {
+                        info!("Moved existing audio to \"{}\"", discarded.display())
+                    }
+                    Err(msg) => {
+                        // never overwrite a recording that couldn't be put somewhere safe
+                        error!("{}. Saving to a new file instead.", msg);
+                        outputfile = sidecar_name.next_free();
+                    }
+                }
+            }
+            ExistingFileStrategy::Append => match flac::decode_file(&outputfile) {
+                Ok(audio) if encoder_config.accepts(&audio) => {
+                    info!(
+                        "Appending to existing audio. Samples: {} Seconds: {:.1}",
+                        audio.samples.len(),
+                        audio.samples.len() as f64 / (audio.sample_rate * audio.channels) as f64
+                    );
+                    carried_samples = audio.samples;
+                }
+                Ok(audio) => {
+                    // never throw away the existing audio, record next to it instead
+                    warn!(
+                        "Existing audio format ({} Hz, {} bit, {} channel) can't be appended to. Saving to a new file instead.",
+                        audio.sample_rate, audio.bits_per_sample, audio.channels
+                    );
+                    outputfile = sidecar_name.next_free();
+                }
+                Err(msg) => {
+                    error!(
+                        "Could not read existing audio to append to: {}. Saving to a new file instead.",
+                        msg
+                    );
+                    outputfile = sidecar_name.next_free();
+                }
+            },
+            ExistingFileStrategy::Ask => unreachable!("the user has already been asked"),
         }
+    }
 
-        let elapsed = frame_time.elapsed().as_secs_f64();
-        if elapsed > max_frame_time {
-            max_frame_time = elapsed;
-        }
+    Some((outputfile, carried_samples))
+}
 
-        // // todo toggle debug text on and off through config file
-        // let mut debug_text = format!("frametime: {:.2}ms\n", max_frame_time * 1000.0);
-        // debug_text += format!("fps: {}\n", framespersec).as_str();
-        // debug_text += format!("samples: {}\n", sample_count).as_str();
-        // debug_text += format!(
-        //     "data size: {:.1}MiB\n",
-        //     sample_count as f64 * 4.0 / 1024.0 / 1024.0
-        // )
-        // .as_str();
-        // debug_text += format!(
-        //     "waveform size: {:.1}MiB\n",
-        //     display_waveform.len() as f64 * 4.0 / 1024.0 / 1024.0
-        // )
-        // .as_str();
-        // ui.debug_view(debug_text.as_str());
+// the encoder set up for the format the interface is recorded in, everything but the output path. The file is tagged
+// with where the audio came from and the format it was in there
+fn encoder_config(interface: &Interface) -> flac::EncoderConfig {
+    let format = &interface.format;
+    let mut encoder_config = flac::EncoderConfig::new();
+    encoder_config.set_format(format.sample_rate, format.bits_per_sample, format.channels);
 
-        ui.present();
-        frames += 1;
-        frame_time = Instant::now();
+    for (name, value) in interface.comments() {
+        encoder_config.add_comment(&name, &value);
+    }
+    encoder_config
+}
 
-        if start_sec.elapsed().as_secs_f64() > 1.0 {
-            start_sec = Instant::now();
-            framespersec = frames as f64;
-            frames = 0;
+// every channel after the first gets the next free sidecar name when SplitChannels is set
+fn output_files(
+    config: &ProgramConfig,
+    interface: &Interface,
+    sidecar_name: &SidecarName,
+    outputfile: PathBuf,
+) -> Vec<PathBuf> {
+    let mut outputfiles = vec![outputfile];
+    if config.split_channels {
+        for _ in 1..interface.format.channels {
+            outputfiles.push(sidecar_name.next_free_except(&outputfiles));
         }
     }
+    outputfiles
 }
 
-fn save_and_quit(
-    config: &ProgramConfig,
-    ui: &UI,
-    encoder: Encoder,
-    logical_interface_id: SDL_AudioDeviceID,
-    audio_stream: *mut SDL_AudioStream,
-    sample_count: u64,
-    mut max_sample_amplitude: u32,
-) {
-    info!("Shutdown triggered");
+// open the encoders and write the audio that must come before anything new. Several output files means each
+// channel is recorded into its own
+fn start_recording<'a>(
+    outputfiles: &[PathBuf],
+    interface: &'a mut Interface,
+    carried_samples: &[i32],
+) -> Recording<'a> {
+    let mut encoders = Vec::with_capacity(outputfiles.len());
+
+    for (channel, outputfile) in outputfiles.iter().enumerate() {
+        info!(
+            "Saving audio to \"{}\" (recording into \"{}\" until done)",
+            outputfile.display(),
+            flac::partial_path(outputfile).display()
+        );
 
-    debug!("Capturing final audio samples...");
+        let mut encoder_config = encoder_config(interface);
+        if outputfiles.len() > 1 {
+            let format = &interface.format;
+            info!("Channel {} is saved on its own", channel + 1);
+            encoder_config.set_format(format.sample_rate, format.bits_per_sample, 1);
+        }
+        encoder_config.set_output_path(outputfile);
 
-    if let Err(msg) = sdl::flush_audio_stream(audio_stream) {
-        die(format!("SDL could not flush audio stream: {}", msg).as_str());
+        encoders.push(match encoder_config.get_encoder() {
+            Ok(e) => e,
+            Err(msg) => die(msg.as_str()),
+        });
     }
-    sdl::close_audio_device(logical_interface_id);
 
-    // get last bit of audio
-    let samples = match sdl::get_audio_stream_data_i32(audio_stream, config.shift_gain) {
-        Ok(s) => s,
-        Err(msg) => die(format!("SDL GetAudioStreamData failed: {}", msg).as_str()),
-    };
+    let mut recording = Recording::new(interface, encoders);
+    if !carried_samples.is_empty() {
+        recording.encode(carried_samples);
+    }
+    recording
+}
+
+// close without saving anything
+fn cancel(ui: &UI, interface: &mut Interface) {
+    info!("Recording cancelled");
 
-    for s in samples.iter() {
-        if s.saturating_abs() as u32 > max_sample_amplitude {
-            max_sample_amplitude = s.saturating_abs() as u32;
+    interface.close();
+    ui.hide();
+    sdl::quit();
+
+    info!("============= Exited =============");
+}
+
+// track the mouse and window size for the ui
+fn handle_ui_event(
+    event: &Event,
+    input: &mut Input,
+    window_width: &mut u32,
+    window_height: &mut u32,
+) {
+    match event {
+        // todo New events will have to be added both here and in sdl::poll_event()
+        Event::Window(event_type, e) if *event_type == SDL_EventType::WINDOW_RESIZED => {
+            *window_width = e.data1 as u32;
+            *window_height = e.data2 as u32;
+        }
+        Event::Button(event_type, _e) => {
+            if *event_type == SDL_EventType::MOUSE_BUTTON_DOWN {
+                input.mouse_button_pressed = true;
+            } else if *event_type == SDL_EventType::MOUSE_BUTTON_UP {
+                input.mouse_button_pressed = false;
+            }
+        }
+        Event::Motion(event_type, e) if *event_type == SDL_EventType::MOUSE_MOTION => {
+            input.mouse_x = e.x;
+            input.mouse_y = e.y;
         }
+        _ => {}
     }
+}
 
-    debug!("Finalizing audio to disk...");
+type DeviceEvent = (SDL_EventType, SDL_AudioDeviceEvent);
+
+// show a question with a row of buttons and wait until one is pressed, calling capture every frame so audio isn't lost meanwhile
+// capture is given the audio device events since the last frame and returns whether the interface is disconnected
+// the first line is the question and any following lines are drawn smaller as details
+// returns the index of the chosen button or None if the window was closed or the program was signalled to quit
+fn prompt(
+    ui: &mut UI,
+    input: &mut Input,
+    window_width: &mut u32,
+    window_height: &mut u32,
+    lines: &[&str],
+    choices: &[&str],
+    capture: &mut dyn FnMut(&[DeviceEvent]) -> bool,
+) -> Option<usize> {
+    let mut device_events = Vec::new();
+    loop {
+        device_events.clear();
+        while let Some(event) = sdl::poll_event() {
+            match event {
+                Event::Quit(_) => return None,
+                Event::ADevice(event_type, e) => device_events.push((event_type, e)),
+                event => handle_ui_event(&event, input, window_width, window_height),
+            }
+        }
 
-    or_die(encoder.encode(&samples));
-    or_die(encoder.finish());
+        if signals::shutdown_requested() {
+            info!("Received signal to quit");
+            return None;
+        }
 
-    info!(
-        "Audio saved. Samples: {} Seconds: {:.1} Max Amplitude: {} Max Amplitude Bits: {:.2}",
-        sample_count,
-        sample_count as f64 / 44100.0,
-        max_sample_amplitude,
-        (max_sample_amplitude as f64).log2()
+        ui.apply_input(input);
 
-    );
-    ui.hide();
+        let disconnected = capture(&device_events);
 
-    let success_sound = match sdl::loadwav("success.wav") {
-        Ok(a) => a,
-        Err(msg) => die(format!("SDL Failed to load wav: {}", msg).as_str()),
-    };
+        ui.clear();
 
-    debug!("Playing success sound...");
-    sdl::play_sound(&success_sound); // todo make configurable
+        let mut y = BORDER_SIZE * 3.0;
+        let notice = disconnected.then_some(DISCONNECTED_NOTICE);
+        for (i, line) in lines.iter().chain(notice.iter()).enumerate() {
+            let size = if i == 0 { 3.0 } else { 2.0 };
+            ui.draw_text(line, BORDER_SIZE * 2.0, y, size, false, false);
+            y += size * 8.0 + BORDER_SIZE * 2.0;
+        }
 
-    sdl::quit();
+        let control_start_y = *window_height as f32 - CONTROL_HEIGHT - BORDER_SIZE;
+        let button_width = (*window_width as f32 - BORDER_SIZE * (choices.len() + 1) as f32)
+            / choices.len() as f32;
+
+        let mut chosen = None;
+        for (i, choice) in choices.iter().enumerate() {
+            if ui.button(
+                choice,
+                BORDER_SIZE + i as f32 * (button_width + BORDER_SIZE),
+                control_start_y,
+                button_width,
+                CONTROL_HEIGHT,
+            ) {
+                info!("pressed \"{}\" button", choice);
+                chosen = Some(i);
+            }
+        }
 
-    info!("============= Exited =============");
+        ui.present();
 
-    // exit(0); // todo avoid exiting the program with exit() to allow things to drop, etc.
+        if chosen.is_some() {
+            return chosen;
+        }
+    }
 }
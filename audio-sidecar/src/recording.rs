@@ -0,0 +1,370 @@
+use crate::config::{AudioFormat, FormatRequest};
+use crate::flac::Encoder;
+use crate::sdl;
+use crate::utils::{die, or_die};
+use log::{debug, error, info, warn};
+use sdl3_sys::everything::*;
+use std::path::PathBuf;
+
+// samples quieter than this count as silence, about -50 dBFS
+pub fn silence_threshold(format: &AudioFormat) -> u32 {
+    format.max_amplitude() / 316
+}
+
+// how loud some audio is, as the RMS and peak amplitude of its samples
+pub fn levels(samples: &[i32]) -> (f64, u32) {
+    let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    let squares: f64 = samples.iter().map(|s| (*s as f64).powi(2)).sum();
+    let rms = (squares / samples.len().max(1) as f64).sqrt();
+    (rms, peak)
+}
+
+// amplitude in dBFS
+pub fn dbfs(amplitude: f64, format: &AudioFormat) -> f64 {
+    20.0 * (amplitude / format.max_amplitude() as f64).log10()
+}
+
+// the audio interface being recorded from. It outlives each recording so narration carries on into the next file,
+// and is reopened if it's unplugged and comes back
+pub struct Interface {
+    pub name: String,
+    pub format: AudioFormat, // what's recorded, the interface's own format unless the config asks for another
+    device_format: Option<sdl::AudioDeviceFormat>, // what the interface gives, if SDL knows
+    device_id: SDL_AudioDeviceID, // the physical device, or the default recording device
+    logical_id: SDL_AudioDeviceID, // 0 while disconnected
+    stream: *mut SDL_AudioStream,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DeviceChange {
+    Disconnected,
+    Reconnected,
+}
+
+impl Interface {
+    pub fn open(
+        device_id: SDL_AudioDeviceID,
+        name: String,
+        request: &FormatRequest,
+    ) -> Result<Self, String> {
+        let device_format = sdl::get_audio_device_format(device_id)
+            .inspect_err(|msg| warn!("Could not get the format of \"{}\": {}", name, msg))
+            .ok();
+        let native = device_format.as_ref().and_then(|f| f.native());
+        let format = request.resolve(native)?;
+
+        match native {
+            Some(native) if native != format => {
+                info!("Converting \"{}\" from {} to {}", name, native, format)
+            }
+            _ => info!("Recording \"{}\" at {}", name, format),
+        }
+
+        let stream = sdl::create_audio_stream(&format)
+            .map_err(|msg| format!("SDL could not create audio stream: {}", msg))?;
+
+        let mut interface = Interface {
+            name,
+            format,
+            device_format,
+            device_id,
+            logical_id: 0,
+            stream,
+        };
+        interface.connect(device_id)?;
+
+        Ok(interface)
+    }
+
+    // the device is opened in its own format, any conversion is left to the stream
+    fn connect(&mut self, device_id: SDL_AudioDeviceID) -> Result<(), String> {
+        let logical_id = sdl::open_audio_device(device_id)
+            .map_err(|msg| format!("SDL could not open audio device: {}", msg))?;
+
+        if let Err(msg) = sdl::bind_audio_stream(logical_id, self.stream) {
+            sdl::close_audio_device(logical_id);
+            return Err(format!(
+                "SDL could not bind logical audio device to stream: {}",
+                msg
+            ));
+        }
+
+        self.logical_id = logical_id;
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.logical_id != 0
+    }
+
+    pub fn device_format(&self) -> Option<&sdl::AudioDeviceFormat> {
+        self.device_format.as_ref()
+    }
+
+    // all audio captured since the last read. A failed read loses that audio but not the recording
+    pub fn read(&self, shift_gain: i32) -> Vec<i32> {
+        match sdl::get_audio_stream_data_i32(self.stream, &self.format, shift_gain) {
+            Ok(s) => s,
+            Err(msg) => {
+                error!("SDL GetAudioStreamData failed: {}", msg);
+                Vec::new()
+            }
+        }
+    }
+
+    // follow the interface being unplugged and plugged back in
+    pub fn handle_device_event(
+        &mut self,
+        event_type: SDL_EventType,
+        event: &SDL_AudioDeviceEvent,
+    ) -> Option<DeviceChange> {
+        if !event.recording {
+            return None;
+        }
+
+        if event_type == SDL_EventType::AUDIO_DEVICE_REMOVED
+            && self.is_connected()
+            && (event.which == self.logical_id || event.which == self.device_id)
+        {
+            warn!("Audio interface \"{}\" disconnected", self.name);
+
+            // what was captured before it went away stays in the stream to be read
+            sdl::unbind_audio_stream(self.stream);
+            self.close();
+
+            return Some(DeviceChange::Disconnected);
+        }
+
+        // the physical device comes back with a new id, so it's recognised by name. The default device is whatever
+        // SDL picks next
+        let is_default = self.device_id == SDL_AUDIO_DEVICE_DEFAULT_RECORDING;
+        if event_type == SDL_EventType::AUDIO_DEVICE_ADDED
+            && !self.is_connected()
+            && (is_default || sdl::get_audio_device_name(event.which).is_ok_and(|n| n == self.name))
+        {
+            let device_id = if is_default {
+                self.device_id
+            } else {
+                event.which
+            };
+
+            return match self.connect(device_id) {
+                Ok(()) => {
+                    info!("Audio interface \"{}\" reconnected", self.name);
+                    self.device_id = device_id;
+                    Some(DeviceChange::Reconnected)
+                }
+                Err(msg) => {
+                    error!("Could not reopen audio interface: {}", msg);
+                    None
+                }
+            };
+        }
+
+        None
+    }
+
+    // the configured interface wasn't found, so SDL picks the device
+    pub fn is_default(&self) -> bool {
+        self.device_id == SDL_AUDIO_DEVICE_DEFAULT_RECORDING
+    }
+
+    // what the recording is tagged with so it's known which device it came from
+    pub fn comments(&self) -> Vec<(String, String)> {
+        let mut comments = vec![("DEVICE".to_string(), self.name.clone())];
+        if let Some(device_format) = &self.device_format {
+            comments.push((
+                "DEVICE_FORMAT".to_string(),
+                format!(
+                    "{} {} Hz {} channel{}",
+                    device_format.format,
+                    device_format.sample_rate,
+                    device_format.channels,
+                    if device_format.channels == 1 { "" } else { "s" }
+                ),
+            ));
+        }
+        comments
+    }
+
+    // carry on from another device, what was captured so far stays in the stream
+    pub fn switch_to(&mut self, device_id: SDL_AudioDeviceID, name: String) -> Result<(), String> {
+        let device_format = sdl::get_audio_device_format(device_id).ok();
+        sdl::unbind_audio_stream(self.stream);
+        self.close();
+
+        if let Err(msg) = self.connect(device_id) {
+            // better the old device than none at all
+            if let Err(msg) = self.connect(self.device_id) {
+                warn!("Could not reopen \"{}\" either: {}", self.name, msg);
+            }
+            return Err(msg);
+        }
+
+        info!(
+            "Switched audio interface from \"{}\" to \"{}\"",
+            self.name, name
+        );
+        self.device_id = device_id;
+        self.device_format = device_format;
+        self.name = name;
+        Ok(())
+    }
+
+    pub fn close(&mut self) {
+        if self.is_connected() {
+            sdl::close_audio_device(self.logical_id);
+            self.logical_id = 0;
+        }
+    }
+
+    // close it for good, for interfaces that were only opened to listen to
+    pub fn discard(mut self) {
+        self.close();
+        sdl::destroy_audio_stream(self.stream);
+    }
+}
+
+// a recording from an open audio interface into a sidecar
+pub struct Recording<'a> {
+    interface: &'a mut Interface,
+    encoders: Vec<Encoder>, // one for all channels, or one per channel when they're split
+    sample_count: u64,
+    max_sample_amplitude: u32,
+}
+
+impl<'a> Recording<'a> {
+    pub fn new(interface: &'a mut Interface, encoders: Vec<Encoder>) -> Self {
+        Recording {
+            interface,
+            encoders,
+            sample_count: 0,
+            max_sample_amplitude: 0,
+        }
+    }
+
+    pub fn read(&self, shift_gain: i32) -> Vec<i32> {
+        self.interface.read(shift_gain)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.interface.is_connected()
+    }
+
+    pub fn handle_device_event(
+        &mut self,
+        event_type: SDL_EventType,
+        event: &SDL_AudioDeviceEvent,
+    ) -> Option<DeviceChange> {
+        self.interface.handle_device_event(event_type, event)
+    }
+
+    pub fn interface(&self) -> &Interface {
+        self.interface
+    }
+
+    // enough sound to know the interface works
+    pub fn heard_sound(&self) -> bool {
+        self.max_sample_amplitude > silence_threshold(&self.interface.format)
+    }
+
+    pub fn uses_default_interface(&self) -> bool {
+        self.interface.is_default()
+    }
+
+    pub fn switch_interface(
+        &mut self,
+        device_id: SDL_AudioDeviceID,
+        name: String,
+    ) -> Result<(), String> {
+        self.interface.switch_to(device_id, name)?;
+
+        // the files were started with the old device's tags
+        for encoder in self.encoders.iter_mut() {
+            encoder.set_comments(self.interface.comments());
+        }
+        Ok(())
+    }
+
+    // encode and save to file as we go, keeping track of the loudest sample for the log
+    pub fn encode(&mut self, samples: &[i32]) {
+        if let [encoder] = self.encoders.as_slice() {
+            or_die(encoder.encode(samples));
+        } else {
+            let channels = self.encoders.len();
+            for (channel, encoder) in self.encoders.iter().enumerate() {
+                let channel_samples: Vec<i32> = samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .copied()
+                    .collect();
+                or_die(encoder.encode(&channel_samples));
+            }
+        }
+
+        self.sample_count += samples.len() as u64;
+        for s in samples.iter() {
+            if s.saturating_abs() as u32 > self.max_sample_amplitude {
+                self.max_sample_amplitude = s.saturating_abs() as u32;
+            }
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.interface.format.seconds(self.sample_count)
+    }
+
+    // stop recording and save the sidecars, returning where they were saved. The interface is closed
+    // the audio still buffered in the stream is only kept if keep_buffered is set
+    pub fn finish(mut self, shift_gain: i32, keep_buffered: bool) -> Vec<PathBuf> {
+        info!("Shutdown triggered");
+
+        debug!("Capturing final audio samples...");
+
+        if let Err(msg) = sdl::flush_audio_stream(self.interface.stream) {
+            die(format!("SDL could not flush audio stream: {}", msg).as_str());
+        }
+        self.interface.close();
+
+        // get last bit of audio
+        let samples = self.read(shift_gain);
+        if keep_buffered {
+            self.encode(&samples);
+        }
+
+        self.finish_encoder()
+    }
+
+    // save the sidecar but keep the interface open so the next recording can continue where this one stopped
+    pub fn save(mut self, shift_gain: i32) -> Vec<PathBuf> {
+        let samples = self.read(shift_gain);
+        self.encode(&samples);
+
+        self.finish_encoder()
+    }
+
+    fn finish_encoder(self) -> Vec<PathBuf> {
+        debug!("Finalizing audio to disk...");
+
+        let seconds = self.seconds();
+        let saved = self
+            .encoders
+            .into_iter()
+            .map(|encoder| match encoder.finish() {
+                Ok(path) => path,
+                Err(msg) => die(format!("Could not save the recording: {}", msg).as_str()),
+            })
+            .collect();
+
+        info!(
+            "Audio saved. Samples: {} Seconds: {:.1} Max Amplitude: {} Max Amplitude Bits: {:.2}",
+            self.sample_count,
+            seconds,
+            self.max_sample_amplitude,
+            (self.max_sample_amplitude as f64).log2()
+        );
+
+        saved
+    }
+}
@@ -0,0 +1,134 @@
+use crate::flac::{self, DecodedAudio};
+use crate::sidecar::SidecarName;
+use crate::utils::{sync_directory, try_lock_file};
+use log::{error, info, warn};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// a recording that was never finished because the program died while it was in progress
+pub struct Orphan {
+    pub path: PathBuf,
+    pub audio: DecodedAudio,
+    _lock: File, // keeps another instance from recovering it at the same time
+}
+
+impl Orphan {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(
+            self.audio.samples.len() as f64
+                / self.audio.channels.max(1) as f64
+                / self.audio.sample_rate.max(1) as f64,
+        )
+    }
+}
+
+// find and decode the unfinished recordings left next to this source file's sidecars
+pub fn find_orphans(sidecar_name: &SidecarName) -> Vec<Orphan> {
+    let mut orphans = Vec::new();
+
+    for path in sidecar_name.partial_files() {
+        // every encoder locks its partial file, so only one nobody has locked was left behind. A paused recording
+        // isn't written to for a while but is still locked
+        let lock = match try_lock_file(&path) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                info!(
+                    "Skipping \"{}\" since another instance is still recording it",
+                    path.display()
+                );
+                continue;
+            }
+            Err(e) => {
+                warn!("Could not lock \"{}\": {}", path.display(), e);
+                continue;
+            }
+        };
+
+        match flac::recover_file(&path) {
+            Ok(audio) if audio.samples.is_empty() => {
+                info!(
+                    "Removing unfinished recording \"{}\" since it contains no audio",
+                    path.display()
+                );
+                if let Err(e) = std::fs::remove_file(&path) {
+                    error!("Could not remove \"{}\": {}", path.display(), e);
+                }
+            }
+            Ok(audio) => {
+                let orphan = Orphan {
+                    path,
+                    audio,
+                    _lock: lock,
+                };
+                info!(
+                    "Found unfinished recording \"{}\". Samples: {} Seconds: {:.1}",
+                    orphan.path.display(),
+                    orphan.audio.samples.len() / orphan.audio.channels.max(1) as usize,
+                    orphan.duration().as_secs_f64()
+                );
+                orphans.push(orphan);
+            }
+            // leave it alone so nothing is lost, maybe it can be recovered by hand
+            Err(msg) => error!(
+                "Could not recover unfinished recording \"{}\": {}",
+                path.display(),
+                msg
+            ),
+        }
+    }
+
+    orphans
+}
+
+// where an orphan is moved while it's recovered into the sidecar it was recording,
+// /dir/.name_audio.flac.partial goes to /dir/.name_audio.flac.partial.recovered.partial
+pub fn aside_path(partial_path: &Path) -> PathBuf {
+    let mut aside_name = partial_path.file_name().unwrap_or_default().to_owned();
+    aside_name.push(".recovered");
+    aside_name.push(flac::PARTIAL_SUFFIX);
+    partial_path.with_file_name(aside_name)
+}
+
+// re-encode the audio into a sidecar under the next free name, which writes a correct STREAMINFO sample count and MD5
+// returns the path of the new sidecar
+pub fn keep(orphan: Orphan, sidecar_name: &SidecarName) -> Result<PathBuf, String> {
+    let output_path = sidecar_name.next_free();
+    let mut source_path = orphan.path;
+
+    // the orphan is sitting where the encoder will write, so move it aside until the new file is saved
+    if flac::partial_path(&output_path) == source_path {
+        let aside_path = aside_path(&source_path);
+
+        std::fs::rename(&source_path, &aside_path).map_err(|e| {
+            format!(
+                "Could not rename \"{}\" to \"{}\": {}",
+                source_path.display(),
+                aside_path.display(),
+                e
+            )
+        })?;
+        source_path = aside_path;
+    }
+
+    let mut encoder_config = flac::EncoderConfig::new();
+    encoder_config.set_format(
+        orphan.audio.sample_rate,
+        orphan.audio.bits_per_sample,
+        orphan.audio.channels,
+    );
+    encoder_config.set_output_path(&output_path);
+
+    let encoder = encoder_config.get_encoder()?;
+    encoder.encode(&orphan.audio.samples)?;
+    encoder.finish()?;
+
+    // only now that the new sidecar is safely on disk can the old data go
+    std::fs::remove_file(&source_path)
+        .map_err(|e| format!("Could not remove \"{}\": {}", source_path.display(), e))?;
+    if let Some(dir) = source_path.parent() {
+        sync_directory(dir).map_err(|e| format!("Could not sync \"{}\": {}", dir.display(), e))?;
+    }
+
+    Ok(output_path)
+}
@@ -1,14 +1,19 @@
+use crate::config::AudioFormat;
 use sdl3_sys::everything::*;
 use sdl3_sys::init::SDL_InitFlags;
 use std::cmp::min;
 use std::ffi::{CStr, CString};
 use std::ptr;
 
-const AUDIO_SPEC: SDL_AudioSpec = SDL_AudioSpec {
-    channels: 1,
-    freq: 44100,
-    format: SDL_AudioFormat::S32,
-};
+// audio is always captured as 32 bit and cut down to the recorded bits per sample when read. Going through 32 bit
+// loses nothing, so when the rest matches the device's own format SDL doesn't have to convert anything
+fn audio_spec(format: &AudioFormat) -> SDL_AudioSpec {
+    SDL_AudioSpec {
+        channels: format.channels as i32,
+        freq: format.sample_rate as i32,
+        format: SDL_AudioFormat::S32,
+    }
+}
 
 pub struct Gfx {
     window: *mut SDL_Window,
@@ -153,6 +158,7 @@ impl Gfx {
 
 // unsafe {SDL_SetWindowOpacity(gfx.window, 0.5)};
 
+#[derive(Clone)]
 pub struct AudioDevice {
     pub id: SDL_AudioDeviceID,
     pub name: String,
@@ -253,8 +259,70 @@ pub fn get_audio_recording_devices() -> Result<Vec<AudioDevice>, String> {
     }
 }
 
+pub fn get_audio_device_name(id: SDL_AudioDeviceID) -> Result<String, String> {
+    let name = unsafe { SDL_GetAudioDeviceName(id) };
+
+    if name.is_null() {
+        Err(get_error())
+    } else {
+        Ok(unsafe { CStr::from_ptr(name).to_string_lossy().to_string() })
+    }
+}
+
+// the format a device prefers, which SDL converts from when it differs from what's recorded
+#[derive(Clone)]
+pub struct AudioDeviceFormat {
+    pub format: String,
+    pub bits_per_sample: u32,
+    pub is_float: bool,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub buffer_frames: i32,
+}
+
+impl AudioDeviceFormat {
+    // the same audio as FLAC can hold it. Floats from the interface are 24 bit as near as FLAC gets, None if SDL
+    // reports something that can't be recorded
+    pub fn native(&self) -> Option<AudioFormat> {
+        let bits_per_sample = if self.is_float {
+            24
+        } else {
+            self.bits_per_sample
+        };
+        AudioFormat::new(
+            self.sample_rate as u32,
+            bits_per_sample,
+            self.channels as u32,
+        )
+        .ok()
+    }
+}
+
+pub fn get_audio_device_format(id: SDL_AudioDeviceID) -> Result<AudioDeviceFormat, String> {
+    let mut spec = SDL_AudioSpec {
+        format: SDL_AUDIO_UNKNOWN,
+        channels: 0,
+        freq: 0,
+    };
+    let mut buffer_frames = 0;
+
+    ok_or_err(unsafe { SDL_GetAudioDeviceFormat(id, &mut spec, &mut buffer_frames) })?;
+
+    let format = unsafe { CStr::from_ptr(SDL_GetAudioFormatName(spec.format)).to_string_lossy() };
+
+    Ok(AudioDeviceFormat {
+        format: format.to_string(),
+        bits_per_sample: SDL_AUDIO_BITSIZE(spec.format),
+        is_float: SDL_AUDIO_ISFLOAT(spec.format),
+        sample_rate: spec.freq,
+        channels: spec.channels,
+        buffer_frames,
+    })
+}
+
+// opened in the device's own format, a stream bound to it converts to whatever it's asked for
 pub fn open_audio_device(id: SDL_AudioDeviceID) -> Result<SDL_AudioDeviceID, String> {
-    let logical_interface_id = unsafe { SDL_OpenAudioDevice(id, &AUDIO_SPEC) };
+    let logical_interface_id = unsafe { SDL_OpenAudioDevice(id, ptr::null()) };
 
     if logical_interface_id != 0 {
         Ok(logical_interface_id)
@@ -270,8 +338,9 @@ pub fn close_audio_device(id: SDL_AudioDeviceID) {
     }
 }
 
-pub fn create_audio_stream() -> Result<*mut SDL_AudioStream, String> {
-    let audio_steam = unsafe { SDL_CreateAudioStream(&AUDIO_SPEC, &AUDIO_SPEC) };
+pub fn create_audio_stream(format: &AudioFormat) -> Result<*mut SDL_AudioStream, String> {
+    let spec = audio_spec(format);
+    let audio_steam = unsafe { SDL_CreateAudioStream(&spec, &spec) };
 
     if audio_steam.is_null() {
         Err(get_error())
@@ -280,6 +349,12 @@ pub fn create_audio_stream() -> Result<*mut SDL_AudioStream, String> {
     }
 }
 
+pub fn destroy_audio_stream(stream: *mut SDL_AudioStream) {
+    unsafe {
+        SDL_DestroyAudioStream(stream);
+    }
+}
+
 pub fn bind_audio_stream(
     id: SDL_AudioDeviceID,
     stream: *mut SDL_AudioStream,
@@ -287,16 +362,28 @@ pub fn bind_audio_stream(
     ok_or_err(unsafe { SDL_BindAudioStream(id, stream) })
 }
 
+pub fn unbind_audio_stream(stream: *mut SDL_AudioStream) {
+    unsafe {
+        SDL_UnbindAudioStream(stream);
+    }
+}
+
 pub fn flush_audio_stream(stream: *mut SDL_AudioStream) -> Result<(), String> {
     ok_or_err(unsafe { SDL_FlushAudioStream(stream) })
 }
 
-// get all samples of pending audio
+// get all samples of pending audio, interleaved when there are several channels
 // todo enforce audio is in i32 format when calling this function
-pub fn get_audio_stream_data_i32(stream: *mut SDL_AudioStream, shift_gain: i32) -> Result<Vec<i32>, String> {
+pub fn get_audio_stream_data_i32(
+    stream: *mut SDL_AudioStream,
+    format: &AudioFormat,
+    shift_gain: i32,
+) -> Result<Vec<i32>, String> {
     let mut samples = Vec::with_capacity(1024);
 
-    let mut sample_buffer = [0i32; 1024];
+    // whole frames for any number of channels up to 8
+    let mut sample_buffer = [0i32; 840];
+    let shift = 32 - format.bits_per_sample;
     let buffer_bytes = (sample_buffer.len() * 4) as i32;
 
     loop {
@@ -305,7 +392,7 @@ pub fn get_audio_stream_data_i32(stream: *mut SDL_AudioStream, shift_gain: i32)
         };
 
         if bytes_read == -1 {
-            return Err(get_error()); // todo we probably want to handle this better since this could maybe interrupt an in progress recording, say if the audio device got disconnected?
+            return Err(get_error());
         } else if bytes_read == 0 {
             break;
         }
@@ -313,8 +400,8 @@ pub fn get_audio_stream_data_i32(stream: *mut SDL_AudioStream, shift_gain: i32)
         let samples_read = (bytes_read / 4) as usize;
 
         for i in 0..(min(sample_buffer.len(), samples_read)) {
-            // clip audio to 24 bits by removing quietest 8 bits
-            samples.push((sample_buffer[i] << shift_gain) >> 8);
+            // clip audio to the bits per sample by removing the quietest bits
+            samples.push((sample_buffer[i] << shift_gain) >> shift);
         }
     }
 
@@ -346,7 +433,7 @@ pub enum Event {
     GButton(SDL_GamepadButtonEvent),
     GTouchpad(SDL_GamepadTouchpadEvent),
     GSensor(SDL_GamepadSensorEvent),
-    ADevice(SDL_AudioDeviceEvent),
+    ADevice(SDL_EventType, SDL_AudioDeviceEvent),
     CDevice(SDL_CameraDeviceEvent),
     Sensor(SDL_SensorEvent),
     Quit(SDL_QuitEvent),
@@ -387,6 +474,15 @@ pub fn poll_event() -> Option<Event> {
                     event.button
                 }))
             }
+            SDL_EventType::AUDIO_DEVICE_ADDED => {
+                Some(Event::ADevice(SDL_EventType::AUDIO_DEVICE_ADDED, unsafe {
+                    event.adevice
+                }))
+            }
+            SDL_EventType::AUDIO_DEVICE_REMOVED => Some(Event::ADevice(
+                SDL_EventType::AUDIO_DEVICE_REMOVED,
+                unsafe { event.adevice },
+            )),
             _ => Some(Event::User(unsafe { event.user })), // dummy event so we can decern an unimplemented event (in this function) from NO event
         }
     } else {
@@ -0,0 +1,642 @@
+use crate::config::{FilenameTemplate, SharedAudio, TemplatePart};
+use crate::flac::PARTIAL_SUFFIX;
+use crate::utils::{data_home, is_writable_dir, parent_dir, sync_directory, LocalTime};
+use log::{error, info, warn};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// give up after this many tries to avoid infinite loops when searching for names
+const MAX_SIDECARS: u32 = 100;
+
+// longest file name in bytes most filesystems allow
+const NAME_MAX: usize = 255;
+
+// room left for the longest name made from a sidecar name, .<name>.partial.recovered.partial when recovering, see
+// recovery::aside_path
+const RESERVED_NAME_BYTES: usize =
+    ".".len() + PARTIAL_SUFFIX.len() + ".recovered".len() + PARTIAL_SUFFIX.len();
+
+// the names of the audio sidecars for a source file, made from the FilenameTemplate
+// with the default template the first is <stem>_audio.flac and the following are <stem>_audioN.flac
+pub struct SidecarName {
+    dir: PathBuf,
+    template: FilenameTemplate,
+    stem: String,
+    ext: String,
+    name: String,
+    date: String,
+    time: String,
+    device: String,
+}
+
+impl SidecarName {
+    pub fn new(filepath: &Path, dir: &Path, template: &FilenameTemplate, device: &str) -> Self {
+        let mut name = filepath
+            .file_name()
+            .expect("filename should be non-empty")
+            .to_string_lossy()
+            .to_string();
+        let mut stem = filepath
+            .with_extension("")
+            .file_name()
+            .expect("filename should be non-empty")
+            .to_string_lossy()
+            .to_string();
+        let ext = filepath
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let now = LocalTime::now();
+        let mut sidecar_name = SidecarName {
+            dir: dir.to_path_buf(),
+            template: template.clone(),
+            stem: String::new(),
+            ext,
+            name: String::new(),
+            date: format!("{:04}-{:02}-{:02}", now.year, now.month, now.day),
+            time: format!("{:02}-{:02}-{:02}", now.hour, now.minute, now.second),
+            // device names can contain anything, keep them from adding directories
+            device: device.replace('/', "-"),
+        };
+
+        // shorten the end of long names so every sidecar fits, always cutting the same way so they still sort next to the source
+        let fixed_len = sidecar_name.file_name(MAX_SIDECARS + 1).len();
+        let stem_count = template
+            .parts()
+            .iter()
+            .filter(|p| matches!(p, TemplatePart::Stem | TemplatePart::Name))
+            .count()
+            .max(1);
+        let max_stem_len = NAME_MAX.saturating_sub(RESERVED_NAME_BYTES + fixed_len) / stem_count;
+        if stem.len() > max_stem_len || name.len() > max_stem_len {
+            truncate_on_char_boundary(&mut stem, max_stem_len);
+            truncate_on_char_boundary(&mut name, max_stem_len);
+            warn!(
+                "File name is too long for a sidecar, shortening it to \"{}\"",
+                name
+            );
+        }
+        sidecar_name.stem = stem;
+        sidecar_name.name = name;
+
+        sidecar_name
+    }
+
+    fn file_name(&self, n: u32) -> String {
+        let mut file_name = String::new();
+        for part in self.template.parts() {
+            match part {
+                TemplatePart::Text(text) => file_name.push_str(text),
+                TemplatePart::Stem => file_name.push_str(&self.stem),
+                TemplatePart::Ext => file_name.push_str(&self.ext),
+                TemplatePart::Name => file_name.push_str(&self.name),
+                TemplatePart::Number if n <= 1 => {}
+                TemplatePart::Number => file_name.push_str(n.to_string().as_str()),
+                TemplatePart::Date => file_name.push_str(&self.date),
+                TemplatePart::Time => file_name.push_str(&self.time),
+                TemplatePart::Device => file_name.push_str(&self.device),
+            }
+        }
+        file_name
+    }
+
+    // path of the nth sidecar, counting from 1
+    pub fn path(&self, n: u32) -> PathBuf {
+        self.dir.join(self.file_name(n))
+    }
+
+    // whether the file is one of this source's sidecars
+    pub fn is_sidecar(&self, path: &Path) -> bool {
+        let dir = parent_dir(path);
+        dir == self.dir.as_path()
+            && self.matches(&path.file_name().unwrap_or_default().to_string_lossy())
+    }
+
+    // whether any recording has been saved for this source
+    pub fn has_sidecar(&self) -> bool {
+        match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).any(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                !name.starts_with('.') && self.matches(&name)
+            }),
+            // fail safely, assume there is audio so nothing gets recorded over
+            Err(_) => true,
+        }
+    }
+
+    // whether a file name could be a sidecar of this source from any session, when the number, date, time and device
+    // may all have been different
+    fn matches(&self, file_name: &str) -> bool {
+        self.matches_parts(self.template.parts(), file_name)
+    }
+
+    fn matches_parts(&self, parts: &[TemplatePart], rest: &str) -> bool {
+        let Some((part, parts)) = parts.split_first() else {
+            return rest.is_empty();
+        };
+
+        let text = match part {
+            TemplatePart::Text(text) => text.as_str(),
+            TemplatePart::Stem => self.stem.as_str(),
+            TemplatePart::Ext => self.ext.as_str(),
+            TemplatePart::Name => self.name.as_str(),
+            // these vary in length, so try every split of what's left
+            _ => {
+                return (0..=rest.len())
+                    .filter(|i| rest.is_char_boundary(*i))
+                    .any(|i| {
+                        let (value, rest) = rest.split_at(i);
+                        could_fill(part, value) && self.matches_parts(parts, rest)
+                    });
+            }
+        };
+
+        rest.strip_prefix(text)
+            .is_some_and(|rest| self.matches_parts(parts, rest))
+    }
+
+    // find the first sidecar name that isn't taken yet
+    pub fn next_free(&self) -> PathBuf {
+        self.next_free_except(&[])
+    }
+
+    // the next free name that isn't already spoken for, for naming several sidecars before any are written
+    pub fn next_free_except(&self, taken: &[PathBuf]) -> PathBuf {
+        for n in 1..=MAX_SIDECARS {
+            let path = self.path(n);
+            // fail safely, assume conflict if can't determine
+            if !taken.contains(&path) && !std::fs::exists(&path).unwrap_or(true) {
+                return path;
+            }
+        }
+
+        error!(
+            "Failed to find next file name in {} tries. Continuing.",
+            MAX_SIDECARS
+        );
+        self.path(MAX_SIDECARS + 1)
+    }
+
+    // hidden partial files of recordings for this source, see flac::partial_path
+    pub fn partial_files(&self) -> Vec<PathBuf> {
+        match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    name.strip_prefix('.')
+                        .and_then(|n| n.strip_suffix(PARTIAL_SUFFIX))
+                        // left over from an interrupted recovery::keep, see recovery::aside_path
+                        .map(|n| {
+                            n.strip_suffix(".recovered")
+                                .and_then(|n| n.strip_suffix(PARTIAL_SUFFIX))
+                                .unwrap_or(n)
+                        })
+                        .is_some_and(|n| self.matches(n))
+                })
+                .collect(),
+            Err(e) => {
+                error!(
+                    "Could not look for partial files in \"{}\": {}",
+                    self.dir.display(),
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    // shift the existing sidecars up by one so the first name is free for the newest recording
+    // _audio.flac becomes _audio2.flac, _audio2.flac becomes _audio3.flac and so on until the first gap in the numbering
+    pub fn make_first_free(&self) -> Result<(), String> {
+        let mut last = 0;
+        while last < MAX_SIDECARS && std::fs::exists(self.path(last + 1)).unwrap_or(true) {
+            last += 1;
+        }
+
+        if last == MAX_SIDECARS {
+            return Err(format!(
+                "More than {} audio sidecars exist, refusing to rename them",
+                MAX_SIDECARS
+            ));
+        }
+
+        // start with the oldest so every rename goes to a name that is already free
+        for n in (1..=last).rev() {
+            let from = self.path(n);
+            let to = self.path(n + 1);
+            info!("Renaming \"{}\" to \"{}\"", from.display(), to.display());
+
+            rename_no_clobber(&from, &to).map_err(|e| {
+                format!(
+                    "Could not rename \"{}\" to \"{}\": {}",
+                    from.display(),
+                    to.display(),
+                    e
+                )
+            })?;
+        }
+
+        sync_directory(&self.dir)
+            .map_err(|e| format!("Could not sync \"{}\": {}", self.dir.display(), e))
+    }
+}
+
+// where to save the sidecars of a source file. Beside the source unless an output directory is configured, and somewhere
+// under the data directory if that can't be written to, like on a read-only camera card
+// returns the directory and whether it is the fallback
+pub fn output_dir(filepath: &Path, configured: Option<&Path>) -> Result<(PathBuf, bool), String> {
+    let source_dir = parent_dir(filepath);
+
+    let dir = match configured {
+        Some(configured) => source_dir.join(configured),
+        None => source_dir.to_path_buf(),
+    };
+
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        warn!(
+            "Could not create output directory \"{}\": {}",
+            dir.display(),
+            e
+        );
+    }
+    if is_writable_dir(&dir) {
+        return Ok((dir, false));
+    }
+
+    // mirror the source's location so sidecars of files with the same name in different folders don't mix
+    let source_dir = source_dir
+        .canonicalize()
+        .map_err(|e| format!("Could not find \"{}\": {}", source_dir.display(), e))?;
+    let fallback = data_home()
+        .ok_or(String::from("Neither XDG_DATA_HOME nor HOME are set"))?
+        .join("audio-sidecar")
+        .join(source_dir.strip_prefix("/").unwrap_or(&source_dir));
+
+    warn!(
+        "Can't write to \"{}\", saving to \"{}\" instead",
+        dir.display(),
+        fallback.display()
+    );
+
+    std::fs::create_dir_all(&fallback)
+        .map_err(|e| format!("Could not create \"{}\": {}", fallback.display(), e))?;
+
+    Ok((fallback, true))
+}
+
+// give another file a sidecar with the audio of one that was just saved, never replacing anything at target
+pub fn share(saved: &Path, target: &Path, how: SharedAudio) -> Result<(), String> {
+    let shared = match how {
+        SharedAudio::Hardlink => std::fs::hard_link(saved, target).or_else(|e| {
+            // hard links don't work across filesystems or on FAT camera cards
+            warn!(
+                "Could not link \"{}\" to \"{}\": {}. Copying it instead.",
+                target.display(),
+                saved.display(),
+                e
+            );
+            copy_no_clobber(saved, target)
+        }),
+        SharedAudio::Symlink => {
+            // relative when possible so the files can be moved together
+            let link_to = if saved.parent() == target.parent() {
+                PathBuf::from(saved.file_name().unwrap_or_default())
+            } else {
+                saved.canonicalize().unwrap_or(saved.to_path_buf())
+            };
+            std::os::unix::fs::symlink(link_to, target)
+        }
+        SharedAudio::Copy => copy_no_clobber(saved, target),
+    };
+
+    shared.map_err(|e| {
+        format!(
+            "Could not share \"{}\" as \"{}\": {}",
+            saved.display(),
+            target.display(),
+            e
+        )
+    })?;
+
+    let dir = parent_dir(target);
+    sync_directory(dir).map_err(|e| format!("Could not sync \"{}\": {}", dir.display(), e))
+}
+
+fn copy_no_clobber(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = File::open(from)?;
+    let mut copy = OpenOptions::new().write(true).create_new(true).open(to)?;
+
+    let copied = io::copy(&mut source, &mut copy).and_then(|_| copy.sync_all());
+    if copied.is_err() {
+        // don't leave half a sidecar behind
+        let _ = std::fs::remove_file(to);
+    }
+    copied
+}
+
+// other files in the same directory that differ only by extension, like photo.png for photo.jpg
+// these share sidecar names unless the extension is part of the template
+pub fn same_stem_siblings(filepath: &Path) -> Vec<PathBuf> {
+    let stem = filepath.with_extension("");
+    let dir = parent_dir(filepath);
+
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| !t.is_dir()))
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name() != filepath.file_name()
+                    && path.with_extension("").file_name() == stem.file_name()
+            })
+            .collect(),
+        Err(e) => {
+            error!(
+                "Could not look for similarly named files in \"{}\": {}",
+                dir.display(),
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+// whether a placeholder that changes between sessions could have been filled in with value
+fn could_fill(part: &TemplatePart, value: &str) -> bool {
+    // digits where there are #s, anything else must be as is
+    let fits = |pattern: &str| {
+        value.len() == pattern.len()
+            && value.chars().zip(pattern.chars()).all(|(v, p)| {
+                if p == '#' {
+                    v.is_ascii_digit()
+                } else {
+                    v == p
+                }
+            })
+    };
+
+    match part {
+        // empty for the first, then 2, 3, 4... without leading zeros
+        TemplatePart::Number => {
+            value.is_empty()
+                || (!value.starts_with('0')
+                    && value.chars().all(|c| c.is_ascii_digit())
+                    && value.parse::<u32>().is_ok_and(|n| n >= 2))
+        }
+        TemplatePart::Date => fits("####-##-##"),
+        TemplatePart::Time => fits("##-##-##"),
+        // device names can be anything
+        TemplatePart::Device => true,
+        TemplatePart::Text(_) | TemplatePart::Stem | TemplatePart::Ext | TemplatePart::Name => {
+            false
+        }
+    }
+}
+
+fn truncate_on_char_boundary(s: &mut String, len: usize) {
+    if s.len() > len {
+        let mut end = len;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+    }
+}
+
+// rename without ever replacing an existing file. Linking first means a crash leaves the recording under both names rather than neither
+fn rename_no_clobber(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::hard_link(from, to) {
+        Ok(()) => std::fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // a previous rename was interrupted between linking and removing the old name
+            let from_meta = std::fs::metadata(from)?;
+            let to_meta = std::fs::metadata(to)?;
+            if from_meta.dev() == to_meta.dev() && from_meta.ino() == to_meta.ino() {
+                std::fs::remove_file(from)
+            } else {
+                Err(e)
+            }
+        }
+        Err(_) => {
+            // filesystems such as FAT on camera cards don't support hard links
+            if std::fs::exists(to)? {
+                Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            } else {
+                std::fs::rename(from, to)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn sidecar_name(source: &str, template: &str) -> SidecarName {
+        let template = FilenameTemplate::from_str(template).unwrap();
+        SidecarName::new(Path::new(source), Path::new("."), &template, "Scarlett 2i2")
+    }
+
+    // every name made for a sidecar fits in NAME_MAX, including while it's recorded and recovered
+    fn assert_names_fit(sidecar_name: &SidecarName) {
+        for n in 1..=MAX_SIDECARS + 1 {
+            let path = sidecar_name.path(n);
+            let partial = crate::flac::partial_path(&path);
+            let aside = crate::recovery::aside_path(&partial);
+            for path in [path, partial, aside] {
+                let len = path.file_name().unwrap().len();
+                assert!(len <= NAME_MAX, "{} is {} bytes", path.display(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn short_names_are_kept() {
+        let photo = sidecar_name("photo.jpg", "{stem}_audio{n}.flac");
+        assert_eq!(photo.path(1), Path::new("./photo_audio.flac"));
+        assert_eq!(photo.path(2), Path::new("./photo_audio2.flac"));
+        assert_names_fit(&photo);
+    }
+
+    #[test]
+    fn long_names_are_shortened_to_fit() {
+        for len in [200, 220, 230, 250, 255] {
+            let source = format!("{}.jpg", "a".repeat(len - 4));
+            for template in ["{stem}_audio{n}.flac", "{name}_audio{n}.flac"] {
+                let sidecar_name = sidecar_name(&source, template);
+                assert_names_fit(&sidecar_name);
+                // shortened from the end so it still sorts beside the source
+                let first = sidecar_name.path(1);
+                assert!(first.to_string_lossy().starts_with("./aaaa"));
+                assert!(first.to_string_lossy().ends_with("_audio.flac"));
+            }
+        }
+    }
+
+    #[test]
+    fn long_names_are_cut_between_characters() {
+        // three bytes each, so the limit falls inside one of them
+        for extra in 0..3 {
+            let source = format!("{}{}.jpg", "a".repeat(extra), "日".repeat(83));
+            for template in ["{stem}_audio{n}.flac", "{name}_{device}{n}.flac"] {
+                let sidecar_name = sidecar_name(&source, template);
+                assert_names_fit(&sidecar_name);
+                assert!(sidecar_name.stem.len() < source.len());
+                assert!(sidecar_name.stem.chars().skip(extra).all(|c| c == '日'));
+            }
+        }
+    }
+
+    #[test]
+    fn every_copy_of_the_name_is_shortened() {
+        let source = format!("{}.jpg", "b".repeat(240));
+        let sidecar_name = sidecar_name(&source, "{stem}_{name}_{date}_{time}{n}.flac");
+        assert_names_fit(&sidecar_name);
+        assert!(sidecar_name.stem.len() < 120);
+    }
+
+    // an empty scratch directory for tests that need files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "audio-sidecar-unit-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn sidecars_are_shifted_up_to_the_first_gap() {
+        let dir = test_dir("shift");
+        for (name, contents) in [
+            ("photo_audio.flac", "first"),
+            ("photo_audio2.flac", "second"),
+            ("photo_audio4.flac", "fourth"),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let template = FilenameTemplate::from_str("{stem}_audio{n}.flac").unwrap();
+        let sidecar_name = SidecarName::new(Path::new("photo.jpg"), &dir, &template, "");
+        sidecar_name.make_first_free().unwrap();
+
+        assert!(!dir.join("photo_audio.flac").exists());
+        assert_eq!(read(dir.join("photo_audio2.flac")), "first");
+        assert_eq!(read(dir.join("photo_audio3.flac")), "second");
+        assert_eq!(read(dir.join("photo_audio4.flac")), "fourth");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_shift_is_finished() {
+        let dir = test_dir("interrupted-shift");
+        std::fs::write(dir.join("photo_audio.flac"), "first").unwrap();
+        std::fs::write(dir.join("photo_audio2.flac"), "second").unwrap();
+        // the last run linked _audio2 to _audio3 and died before removing _audio2
+        std::fs::hard_link(dir.join("photo_audio2.flac"), dir.join("photo_audio3.flac")).unwrap();
+
+        // finishing that rename is safe since both names are the same file
+        rename_no_clobber(
+            &dir.join("photo_audio2.flac"),
+            &dir.join("photo_audio3.flac"),
+        )
+        .unwrap();
+        assert!(!dir.join("photo_audio2.flac").exists());
+        assert_eq!(read(dir.join("photo_audio3.flac")), "second");
+
+        // a different file is never replaced
+        let err = rename_no_clobber(
+            &dir.join("photo_audio.flac"),
+            &dir.join("photo_audio3.flac"),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read(dir.join("photo_audio.flac")), "first");
+        assert_eq!(read(dir.join("photo_audio3.flac")), "second");
+
+        let template = FilenameTemplate::from_str("{stem}_audio{n}.flac").unwrap();
+        let sidecar_name = SidecarName::new(Path::new("photo.jpg"), &dir, &template, "");
+        sidecar_name.make_first_free().unwrap();
+
+        assert!(!dir.join("photo_audio.flac").exists());
+        assert_eq!(read(dir.join("photo_audio2.flac")), "first");
+        assert_eq!(read(dir.join("photo_audio3.flac")), "second");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn partial_files_are_found() {
+        let dir = test_dir("partials");
+        for name in [
+            ".photo_audio.flac.partial",
+            ".photo_audio2.flac.partial.recovered.partial",
+            ".photo_notes_audio.flac.partial",
+            "photo_audio.flac",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let template = FilenameTemplate::from_str("{stem}_audio{n}.flac").unwrap();
+        let sidecar_name = SidecarName::new(Path::new("photo.jpg"), &dir, &template, "");
+        let mut found = sidecar_name.partial_files();
+        found.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            found,
+            [
+                dir.join(".photo_audio.flac.partial"),
+                dir.join(".photo_audio2.flac.partial.recovered.partial"),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbered_sidecars_match() {
+        let trip = sidecar_name("trip.jpg", "{stem}_audio{n}.flac");
+        assert!(trip.matches("trip_audio.flac"));
+        assert!(trip.matches("trip_audio2.flac"));
+        assert!(trip.matches("trip_audio37.flac"));
+
+        assert!(!trip.matches("trip_audio1.flac"));
+        assert!(!trip.matches("trip_audio02.flac"));
+        assert!(!trip.matches("trip_audio.wav"));
+        // the sidecar of trip_audio_notes.jpg
+        assert!(!trip.matches("trip_audio_notes_audio.flac"));
+        assert!(!trip.matches("trip_audio_notes_audio2.flac"));
+    }
+
+    #[test]
+    fn dates_and_times_match_their_format() {
+        let trip = sidecar_name("trip.jpg", "{stem}_{date}_{time}{n}.flac");
+        assert!(trip.matches("trip_2024-07-01_09-30-05.flac"));
+        assert!(trip.matches("trip_2025-01-31_14-05-09.flac"));
+        assert!(trip.matches("trip_2025-01-31_14-05-093.flac"));
+
+        assert!(!trip.matches("trip_notes_2025-01-31_14-05-09.flac"));
+        assert!(!trip.matches("trip_2025-1-31_14-05-09.flac"));
+        assert!(!trip.matches("trip_2025-01-31_14:05:09.flac"));
+    }
+
+    #[test]
+    fn any_device_matches() {
+        let trip = sidecar_name("trip.jpg", "{stem}_{device}{n}.flac");
+        assert!(trip.matches("trip_Scarlett 2i2.flac"));
+        assert!(trip.matches("trip_USB Audio2.flac"));
+        assert!(!trip.matches("trip.flac"));
+    }
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    // only async signal safe work is allowed here, so just leave a note for the main loop
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// turn closing the terminal, logging out and kill into a request to save and quit
+// this replaces the handlers SDL installs for SIGINT and SIGTERM, which only push a quit event
+pub fn install_handlers() -> Result<(), String> {
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+
+        let result = unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, ptr::null_mut())
+        };
+
+        if result != 0 {
+            return Err(format!(
+                "Could not install handler for signal {}: {}",
+                signal,
+                io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,85 @@
+use crate::utils::state_home;
+use config::{Config, FileFormat};
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+
+// the interface that last made a recording with sound in it. It's preferred next time as long as the Interface
+// setting it was picked with hasn't changed
+pub struct LastDevice {
+    pub name: String,
+    pub interface: String, // the Interface setting as written, see InterfacePatterns
+    pub rank: usize,       // which of its patterns picked the interface, counting from 0
+    pub format: String,
+    pub sample_rate: i32,
+    pub channels: i32,
+}
+
+impl LastDevice {
+    pub fn load() -> Option<LastDevice> {
+        let path = state_file()?;
+        if !path.exists() {
+            return None;
+        }
+
+        let state = Config::builder()
+            .add_source(config::File::new(
+                path.to_string_lossy().as_ref(),
+                FileFormat::Toml,
+            ))
+            .build()
+            .ok()?;
+
+        Some(LastDevice {
+            name: state.get("DeviceName").ok()?,
+            interface: state.get("Interface").ok()?,
+            rank: state.get("Rank").ok()?,
+            format: state.get("Format").unwrap_or_default(),
+            sample_rate: state.get("SampleRate").unwrap_or_default(),
+            channels: state.get("Channels").unwrap_or_default(),
+        })
+    }
+
+    // replaced in one go so a crash never leaves half a file
+    pub fn save(&self) -> Result<(), String> {
+        let path = state_file().ok_or(String::from("no home directory"))?;
+        let dir = path.parent().unwrap_or(&path);
+
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| format!("Could not create \"{}\": {}", dir.display(), e))?;
+
+        let contents = format!(
+            "# written by audio-sidecar, the interface that last recorded sound\n\
+             DeviceName = \"{}\"\nInterface = \"{}\"\nRank = {}\nFormat = \"{}\"\nSampleRate = {}\nChannels = {}\n",
+            toml_escape(&self.name),
+            toml_escape(&self.interface),
+            self.rank,
+            toml_escape(&self.format),
+            self.sample_rate,
+            self.channels
+        );
+
+        let temp = path.with_extension("toml.tmp");
+        fs::write(&temp, contents)
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| format!("Could not write \"{}\": {}", path.display(), e))
+    }
+}
+
+fn state_file() -> Option<PathBuf> {
+    state_home().map(|dir| dir.join("audio-sidecar/last-device.toml"))
+}
+
+// just enough for a basic TOML string, device names don't have control characters worth keeping
+fn toml_escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control())
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
@@ -0,0 +1,189 @@
+use crate::utils::{data_home, parent_dir, sync_directory, LocalTime};
+use log::warn;
+use std::ffi::OsString;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+// give up after this many tries when looking for a free name in the trash or backup directory
+const MAX_TRIES: u32 = 1000;
+
+// move a file out of the way without deleting it. The trash is tried first so the file can be restored from the file
+// manager. If that fails, usually because the trash is on another filesystem, the file is moved to the backup directory
+// returns where the file ended up
+pub fn discard(path: &Path, backup_dir: Option<&Path>) -> Result<PathBuf, String> {
+    let trash_error = match move_to_trash(path) {
+        Ok(trashed) => return Ok(trashed),
+        Err(e) => format!("Could not move \"{}\" to the trash: {}", path.display(), e),
+    };
+
+    match backup_dir {
+        Some(backup_dir) => {
+            warn!(
+                "{}. Moving it to the backup directory instead.",
+                trash_error
+            );
+            move_to_backup(path, backup_dir).map_err(|e| {
+                format!(
+                    "Could not move \"{}\" to backup directory \"{}\": {}",
+                    path.display(),
+                    backup_dir.display(),
+                    e
+                )
+            })
+        }
+        None => Err(trash_error),
+    }
+}
+
+// $XDG_DATA_HOME/Trash, see https://specifications.freedesktop.org/trash-spec/latest/
+fn home_trash_dir() -> Option<PathBuf> {
+    Some(data_home()?.join("Trash"))
+}
+
+fn move_to_trash(path: &Path) -> io::Result<PathBuf> {
+    let trash_dir = home_trash_dir().ok_or(io::Error::new(
+        io::ErrorKind::NotFound,
+        "neither XDG_DATA_HOME nor HOME are set",
+    ))?;
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&files_dir)?;
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&info_dir)?;
+
+    // canonicalize the parent rather than the file so a symlink is trashed as itself
+    let file_name = path
+        .file_name()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let source_dir = parent_dir(path);
+    let original_path = source_dir.canonicalize()?.join(file_name);
+
+    let now = LocalTime::now();
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={:04}-{:02}-{:02}T{:02}:{:02}:{:02}\n",
+        encode_trash_path(&original_path),
+        now.year,
+        now.month,
+        now.day,
+        now.hour,
+        now.minute,
+        now.second
+    );
+
+    for n in 1..=MAX_TRIES {
+        let trash_name = numbered_name(Path::new(file_name), n);
+        let trashed_path = files_dir.join(&trash_name);
+
+        let mut info_file_name = trash_name.clone();
+        info_file_name.push(".trashinfo");
+        let info_path = info_dir.join(info_file_name);
+
+        // creating the info file reserves the name, this must be atomic according to the spec
+        let mut info_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+
+        let moved = if std::fs::exists(&trashed_path)? {
+            // left over from something that didn't follow the spec, don't replace it
+            Err(io::Error::from(io::ErrorKind::AlreadyExists))
+        } else {
+            info_file
+                .write_all(info.as_bytes())
+                .and_then(|_| info_file.sync_all())
+                .and_then(|_| std::fs::rename(path, &trashed_path))
+        };
+
+        match moved {
+            Ok(()) => {
+                sync_directory(&files_dir)?;
+                sync_directory(source_dir)?;
+                return Ok(trashed_path);
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&info_path);
+                if e.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "could not find a free name in the trash",
+    ))
+}
+
+fn move_to_backup(path: &Path, backup_dir: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(backup_dir)?;
+
+    let file_name = path
+        .file_name()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+
+    let backup_path = (1..=MAX_TRIES)
+        .map(|n| backup_dir.join(numbered_name(Path::new(file_name), n)))
+        .find(|p| !std::fs::exists(p).unwrap_or(true))
+        .ok_or(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "could not find a free name in the backup directory",
+        ))?;
+
+    match std::fs::rename(path, &backup_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            // only remove the original once the copy is safely on disk
+            std::fs::copy(path, &backup_path)?;
+            File::open(&backup_path)?.sync_all()?;
+            std::fs::remove_file(path)?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    sync_directory(backup_dir)?;
+
+    Ok(backup_path)
+}
+
+// name.flac, name.2.flac, name.3.flac...
+fn numbered_name(file_name: &Path, n: u32) -> OsString {
+    if n <= 1 {
+        return file_name.as_os_str().to_owned();
+    }
+
+    let mut name = file_name.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{}", n));
+    if let Some(extension) = file_name.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    name
+}
+
+// percent encode everything but unreserved characters and slashes, as the trash spec requires for the Path key
+fn encode_trash_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(format!("%{:02X}", b).as_str());
+        }
+    }
+    encoded
+}
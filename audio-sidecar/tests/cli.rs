@@ -0,0 +1,186 @@
+mod common;
+
+use common::{read_streaminfo, wait_for_exit, TestDir};
+
+#[test]
+fn version_is_printed() {
+    let dir = TestDir::new("version");
+
+    let output = dir.command().arg("--version").output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("audio_sidecar {}", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn bad_arguments_are_reported() {
+    let dir = TestDir::new("bad-arguments");
+
+    for args in [
+        vec!["--frobnicate", "photo.jpg"],
+        vec!["--strategy", "shred", "photo.jpg"],
+        vec!["--duration", "5", "photo.jpg"],
+        vec!["--device", "re:(", "photo.jpg"],
+        vec!["record"],
+        vec!["record", "/"],
+        vec!["photo.jpg", ".."],
+    ] {
+        let output = dir.command().args(&args).output().unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{:?} was accepted", args);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("Usage:"),
+            "{:?} didn't show usage",
+            args
+        );
+    }
+}
+
+#[test]
+fn flags_override_config() {
+    let dir = TestDir::new("override");
+    std::fs::remove_file(dir.path.join("audio-sidecar-config.toml")).unwrap();
+    std::fs::write(
+        dir.path.join("elsewhere.toml"),
+        "LogFile = \"test.log\"\nExistingFileStrategy = \"append\"\n",
+    )
+    .unwrap();
+
+    for _ in 0..2 {
+        let mut child = dir
+            .headless()
+            .args(["--config", "elsewhere.toml", "--strategy", "rename-to-last"])
+            .args(["--duration", "0.5"])
+            .spawn()
+            .unwrap();
+        let status = wait_for_exit(&mut child);
+        assert!(status.success(), "exited with {}", status);
+    }
+
+    // appending would have made one longer sidecar
+    assert_eq!(
+        read_streaminfo(&dir.path.join("photo_audio.flac")).total_samples,
+        22050
+    );
+    assert!(dir.path.join("photo_audio2.flac").exists());
+}
+
+#[test]
+fn devices_are_listed() {
+    let dir = TestDir::new("list-devices");
+    dir.write_config("Interface = \"no such interface\"\n");
+
+    let output = dir.command().arg("list-devices").output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("   ID  Name"), "no header in {}", stdout);
+    assert!(
+        stdout.contains("No device matches Interface = \"no such interface\""),
+        "selection not shown in {}",
+        stdout
+    );
+}
+
+#[test]
+fn interface_list_is_read() {
+    let dir = TestDir::new("list-devices-patterns");
+    dir.write_config("Interface = [\"no such interface\", \"=Also Missing\"]\n");
+
+    let output = dir.command().arg("list-devices").output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No device matches Interface = [\"no such interface\", \"=Also Missing\"]"),
+        "patterns not shown in {}",
+        stdout
+    );
+}
+
+#[test]
+fn remembered_device_is_outranked() {
+    let dir = TestDir::new("list-devices-remembered");
+    dir.write_config("Interface = [\"recording device\", \"auto\"]\n");
+
+    let state_dir = dir.path.join("state/audio-sidecar");
+    std::fs::create_dir_all(&state_dir).unwrap();
+    let remember = |rank: usize| {
+        std::fs::write(
+            state_dir.join("last-device.toml"),
+            format!(
+                "DeviceName = \"System audio recording device\"\n\
+                 Interface = '[\"recording device\", \"auto\"]'\nRank = {}\n",
+                rank
+            ),
+        )
+        .unwrap();
+    };
+
+    // picked by the first pattern, which still matches it
+    remember(0);
+    let output = dir.command().arg("list-devices").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("recorded with last time"),
+        "remembered device not used in {}",
+        stdout
+    );
+
+    // picked by auto, but the first pattern has a device now
+    remember(1);
+    let output = dir.command().arg("list-devices").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("selected by Interface"),
+        "remembered device won over the first pattern in {}",
+        stdout
+    );
+}
+
+#[test]
+fn auto_device_needs_sound() {
+    let dir = TestDir::new("list-devices-auto");
+    dir.write_config("Interface = \"no such interface\"\n");
+
+    // the dummy driver only records silence
+    let output = dir
+        .command()
+        .args(["list-devices", "--device", "auto"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No device is receiving sound"),
+        "silent device selected in {}",
+        stdout
+    );
+}
+
+#[test]
+fn narrate_skips_recorded_files() {
+    let dir = TestDir::new("narrate-recorded");
+    let album = dir.path.join("album");
+    std::fs::create_dir(&album).unwrap();
+    for name in [
+        "photo1.jpg",
+        "photo1_audio.flac",
+        "photo2.jpg",
+        "photo2_audio3.flac",
+    ] {
+        std::fs::write(album.join(name), b"").unwrap();
+    }
+
+    let output = dir.command().arg("narrate").arg(&album).output().unwrap();
+
+    assert!(output.status.success(), "exited with {}", output.status);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Every file already has audio"),
+        "something was left to narrate"
+    );
+}
@@ -0,0 +1,166 @@
+// helpers for running the audio_sidecar binary against SDL's dummy drivers
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+// a scratch directory that is removed when the test is done
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "audio-sidecar-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("test directory to be created");
+
+        // the program loads its config and success sound from the working directory
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../success.wav"),
+            path.join("success.wav"),
+        )
+        .expect("success.wav to be copied");
+
+        let test_dir = TestDir { path };
+        test_dir.write_config("");
+        test_dir
+    }
+
+    // write the config file, extra is appended to settings that keep the test quiet and self contained
+    pub fn write_config(&self, extra: &str) {
+        std::fs::write(
+            self.path.join("audio-sidecar-config.toml"),
+            format!("LogFile = \"test.log\"\nLogLevel = \"debug\"\n{}", extra),
+        )
+        .expect("config to be written");
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_audio_sidecar"));
+        command
+            .current_dir(&self.path)
+            .env("SDL_VIDEO_DRIVER", "dummy")
+            .env("SDL_AUDIO_DRIVER", "dummy")
+            .env("XDG_DATA_HOME", self.path.join("data"))
+            .env("XDG_STATE_HOME", self.path.join("state"))
+            .stdin(Stdio::null());
+        command
+    }
+
+    // record to a sidecar for photo.jpg without a window
+    pub fn headless(&self) -> Command {
+        let source = self.path.join("photo.jpg");
+        std::fs::write(&source, b"").expect("source file to be written");
+
+        let mut command = self.command();
+        command.arg("--headless").arg(&source);
+        command
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn wait_for_file(path: &Path) {
+    let start = Instant::now();
+    while !path.exists() {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "timed out waiting for {}",
+            path.display()
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn wait_for_exit(child: &mut Child) -> ExitStatus {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().expect("child status to be readable") {
+            return status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("timed out waiting for the program to exit");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub struct StreamInfo {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    pub total_samples: u64,
+    pub md5: [u8; 16],
+}
+
+// parse the STREAMINFO block, which always comes first in a flac file
+pub fn read_streaminfo(path: &Path) -> StreamInfo {
+    let data = std::fs::read(path).expect("flac file to be readable");
+
+    assert!(data.len() >= 42, "{} is too short", path.display());
+    assert_eq!(
+        &data[0..4],
+        b"fLaC",
+        "{} is not a flac file",
+        path.display()
+    );
+    assert_eq!(data[4] & 0x7f, 0, "first metadata block is not STREAMINFO");
+
+    let info = &data[8..42];
+    let packed = u64::from_be_bytes(info[10..18].try_into().unwrap());
+
+    StreamInfo {
+        sample_rate: (packed >> 44) as u32,
+        channels: ((packed >> 41) & 0x7) as u32 + 1,
+        bits_per_sample: ((packed >> 36) & 0x1f) as u32 + 1,
+        total_samples: packed & 0xf_ffff_ffff,
+        md5: info[18..34].try_into().unwrap(),
+    }
+}
+
+// the NAME=value entries of the VORBIS_COMMENT block, empty if there isn't one
+pub fn read_vorbis_comments(path: &Path) -> Vec<String> {
+    let data = std::fs::read(path).expect("flac file to be readable");
+    let le_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+
+    let mut at = 4;
+    loop {
+        let header = data[at];
+        let length = u32::from_be_bytes([0, data[at + 1], data[at + 2], data[at + 3]]) as usize;
+        at += 4;
+
+        if header & 0x7f == 4 {
+            let mut entry = at + 4 + le_u32(at);
+            let count = le_u32(entry);
+            entry += 4;
+
+            let mut comments = Vec::with_capacity(count);
+            for _ in 0..count {
+                let length = le_u32(entry);
+                comments.push(
+                    String::from_utf8_lossy(&data[entry + 4..entry + 4 + length]).to_string(),
+                );
+                entry += 4 + length;
+            }
+            return comments;
+        }
+
+        if header & 0x80 != 0 {
+            return Vec::new();
+        }
+        at += length;
+    }
+}
@@ -0,0 +1,231 @@
+mod common;
+
+use common::{read_streaminfo, read_vorbis_comments, wait_for_exit, wait_for_file, TestDir};
+use std::io::Write;
+use std::process::Stdio;
+use std::time::Duration;
+
+#[test]
+fn duration_limits_recording_length() {
+    let dir = TestDir::new("duration");
+
+    let mut child = dir.headless().args(["--duration", "1.5"]).spawn().unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert_eq!(info.total_samples, 44100 * 3 / 2);
+    assert!(!dir.path.join(".photo_audio.flac.partial").exists());
+}
+
+#[test]
+fn native_format_is_recorded() {
+    let dir = TestDir::new("native-format");
+
+    let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    // the dummy driver records 16 bit mono at 44100 Hz
+    let sidecar = dir.path.join("photo_audio.flac");
+    let info = read_streaminfo(&sidecar);
+    assert_eq!(info.sample_rate, 44100);
+    assert_eq!(info.bits_per_sample, 16);
+    assert_eq!(info.channels, 1);
+
+    let comments = read_vorbis_comments(&sidecar);
+    assert!(
+        comments.iter().any(|c| c.starts_with("DEVICE=")),
+        "no DEVICE in {:?}",
+        comments
+    );
+    assert!(
+        comments
+            .iter()
+            .any(|c| c.starts_with("DEVICE_FORMAT=") && c.ends_with("44100 Hz 1 channel")),
+        "no DEVICE_FORMAT in {:?}",
+        comments
+    );
+}
+
+#[test]
+fn configured_format_is_recorded() {
+    let dir = TestDir::new("format");
+    dir.write_config("SampleRate = 48000\nBitsPerSample = 16\nChannels = 2\n");
+
+    let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert_eq!(info.sample_rate, 48000);
+    assert_eq!(info.bits_per_sample, 16);
+    assert_eq!(info.channels, 2);
+    // the duration is per channel, not split between them
+    assert_eq!(info.total_samples, 24000);
+
+    // converted from what the interface gives
+    let comments = read_vorbis_comments(&dir.path.join("photo_audio.flac"));
+    assert!(
+        comments
+            .iter()
+            .any(|c| c.starts_with("DEVICE_FORMAT=") && c.ends_with("44100 Hz 1 channel")),
+        "no DEVICE_FORMAT in {:?}",
+        comments
+    );
+}
+
+#[test]
+fn channels_can_be_split() {
+    let dir = TestDir::new("split-channels");
+    dir.write_config("Channels = 2\nSplitChannels = true\n");
+
+    let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    for name in ["photo_audio.flac", "photo_audio2.flac"] {
+        let info = read_streaminfo(&dir.path.join(name));
+        assert_eq!(info.channels, 1, "{} isn't mono", name);
+        assert_eq!(info.total_samples, 22050, "{} has the wrong length", name);
+    }
+}
+
+#[test]
+fn stereo_can_be_appended_to() {
+    let dir = TestDir::new("format-append");
+    dir.write_config("Channels = 2\nExistingFileStrategy = \"append\"\n");
+
+    for _ in 0..2 {
+        let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+        let status = wait_for_exit(&mut child);
+        assert!(status.success(), "exited with {}", status);
+    }
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert_eq!(info.channels, 2);
+    assert_eq!(info.total_samples, 44100);
+}
+
+#[test]
+fn silence_timeout_stops_recording() {
+    let dir = TestDir::new("silence");
+
+    // the dummy driver records nothing but silence
+    let mut child = dir
+        .headless()
+        .args(["--silence-timeout", "1"])
+        .spawn()
+        .unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert!(info.total_samples >= 44100, "stopped too early");
+
+    // silence says nothing about whether the interface works, so it isn't remembered
+    assert!(!dir
+        .path
+        .join("state/audio-sidecar/last-device.toml")
+        .exists());
+}
+
+#[test]
+fn enter_stops_recording() {
+    let dir = TestDir::new("enter");
+
+    let mut child = dir.headless().stdin(Stdio::piped()).spawn().unwrap();
+
+    wait_for_file(&dir.path.join(".photo_audio.flac.partial"));
+    std::thread::sleep(Duration::from_millis(500));
+
+    child.stdin.take().unwrap().write_all(b"\n").unwrap();
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert!(info.total_samples > 0, "no samples were saved");
+}
+
+#[test]
+fn existing_sidecar_is_kept() {
+    let dir = TestDir::new("existing");
+    dir.write_config("ExistingFileStrategy = \"ask\"\n");
+
+    for _ in 0..2 {
+        let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+        let status = wait_for_exit(&mut child);
+        assert!(status.success(), "exited with {}", status);
+    }
+
+    // nobody can be asked, so the second recording goes next to the first
+    assert_eq!(
+        read_streaminfo(&dir.path.join("photo_audio.flac")).total_samples,
+        22050
+    );
+    assert_eq!(
+        read_streaminfo(&dir.path.join("photo_audio2.flac")).total_samples,
+        22050
+    );
+}
+
+#[test]
+fn recovered_audio_is_not_replaced() {
+    let dir = TestDir::new("recovered-replace");
+    dir.write_config("ExistingFileStrategy = \"replace\"\n");
+
+    let mut child = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    // pass the sidecar off as a recording that was cut short, nothing has it locked
+    let partial = dir.path.join(".photo_audio.flac.partial");
+    std::fs::rename(dir.path.join("photo_audio.flac"), &partial).unwrap();
+
+    let mut child = dir.headless().args(["--duration", "1"]).spawn().unwrap();
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    // headless always keeps what it recovers, and the new recording goes beside it
+    assert_eq!(
+        read_streaminfo(&dir.path.join("photo_audio.flac")).total_samples,
+        22050
+    );
+    assert_eq!(
+        read_streaminfo(&dir.path.join("photo_audio2.flac")).total_samples,
+        44100
+    );
+    assert!(!partial.exists());
+}
+
+#[test]
+fn recording_in_progress_is_left_alone() {
+    let dir = TestDir::new("in-progress");
+
+    let mut first = dir.headless().spawn().unwrap();
+    let partial = dir.path.join(".photo_audio.flac.partial");
+    wait_for_file(&partial);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // however long since it was written to, the first is still recording into it
+    let mut second = dir.headless().args(["--duration", "0.5"]).spawn().unwrap();
+    let status = wait_for_exit(&mut second);
+    assert!(!status.success(), "recorded over another instance");
+    assert!(partial.exists(), "the recording in progress was taken");
+
+    assert_eq!(
+        unsafe { libc::kill(first.id() as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+    let status = wait_for_exit(&mut first);
+    assert!(status.success(), "exited with {}", status);
+
+    let info = read_streaminfo(&dir.path.join("photo_audio.flac"));
+    assert!(info.total_samples > 0, "no samples were saved");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{read_streaminfo, wait_for_exit, TestDir};
+use std::os::unix::fs::MetadataExt;
+
+// record half a second for three photos, the second of which already has audio
+fn record_for_burst(dir: &TestDir) {
+    let photos = ["burst1.jpg", "burst2.jpg", "burst3.jpg"].map(|name| dir.path.join(name));
+    for photo in photos.iter() {
+        std::fs::write(photo, b"").unwrap();
+    }
+    std::fs::write(dir.path.join("burst2_audio.flac"), b"older audio").unwrap();
+
+    let mut child = dir
+        .command()
+        .args(["--headless", "--duration", "0.5", "--"])
+        .args(photos)
+        .spawn()
+        .unwrap();
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    assert_eq!(
+        std::fs::read(dir.path.join("burst2_audio.flac")).unwrap(),
+        b"older audio",
+        "existing audio was touched"
+    );
+    for sidecar in [
+        "burst1_audio.flac",
+        "burst2_audio2.flac",
+        "burst3_audio.flac",
+    ] {
+        assert_eq!(
+            read_streaminfo(&dir.path.join(sidecar)).total_samples,
+            22050,
+            "{} doesn't have the recording",
+            sidecar
+        );
+    }
+}
+
+#[test]
+fn burst_shares_hardlinks() {
+    let dir = TestDir::new("shared-hardlink");
+    record_for_burst(&dir);
+
+    let first = std::fs::metadata(dir.path.join("burst1_audio.flac")).unwrap();
+    let third = std::fs::metadata(dir.path.join("burst3_audio.flac")).unwrap();
+    assert_eq!(first.ino(), third.ino());
+}
+
+#[test]
+fn burst_shares_symlinks() {
+    let dir = TestDir::new("shared-symlink");
+    dir.write_config("SharedAudio = \"symlink\"\n");
+    record_for_burst(&dir);
+
+    assert_eq!(
+        std::fs::read_link(dir.path.join("burst3_audio.flac")).unwrap(),
+        std::path::Path::new("burst1_audio.flac")
+    );
+}
+
+#[test]
+fn burst_shares_copies() {
+    let dir = TestDir::new("shared-copy");
+    dir.write_config("SharedAudio = \"copy\"\n");
+    record_for_burst(&dir);
+
+    let first = std::fs::metadata(dir.path.join("burst1_audio.flac")).unwrap();
+    let third = std::fs::metadata(dir.path.join("burst3_audio.flac")).unwrap();
+    assert_ne!(first.ino(), third.ino());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{read_streaminfo, wait_for_exit, wait_for_file, TestDir};
+use std::time::Duration;
+
+// start recording, send the signal once audio is being written and check a complete sidecar was saved
+fn record_until_signal(name: &str, signal: libc::c_int) {
+    let dir = TestDir::new(name);
+    let mut child = dir.headless().spawn().unwrap();
+
+    wait_for_file(&dir.path.join(".photo_audio.flac.partial"));
+    std::thread::sleep(Duration::from_secs(1));
+
+    assert_eq!(unsafe { libc::kill(child.id() as libc::pid_t, signal) }, 0);
+
+    let status = wait_for_exit(&mut child);
+    assert!(status.success(), "exited with {}", status);
+
+    let sidecar = dir.path.join("photo_audio.flac");
+    assert!(sidecar.exists(), "sidecar was not saved");
+    assert!(!dir.path.join(".photo_audio.flac.partial").exists());
+
+    let info = read_streaminfo(&sidecar);
+    assert_eq!(info.sample_rate, 44100);
+    assert_eq!(info.channels, 1);
+    // the dummy driver records 16 bit, which is kept as it is
+    assert_eq!(info.bits_per_sample, 16);
+    assert!(info.total_samples > 0, "no samples were saved");
+    assert_ne!(info.md5, [0; 16], "MD5 was never written");
+}
+
+#[test]
+fn sigterm_saves_recording() {
+    record_until_signal("sigterm", libc::SIGTERM);
+}
+
+#[test]
+fn sigint_saves_recording() {
+    record_until_signal("sigint", libc::SIGINT);
+}
+
+#[test]
+fn sighup_saves_recording() {
+    record_until_signal("sighup", libc::SIGHUP);
+}